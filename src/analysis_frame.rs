@@ -0,0 +1,200 @@
+use crate::beat_tracking::ChordGrid;
+use crate::chord_recognition::Chord;
+use crate::note_detection::DetectedNote;
+use crate::SpectrumData;
+
+// Most notes a single frame can report
+pub const MAX_FRAME_NOTES: usize = 32;
+
+// Number of log-spaced bins in the spectrum snapshot
+pub const SPECTRUM_SNAPSHOT_BINS: usize = 256;
+
+// Level used for empty snapshot bins and silent meters
+pub const FLOOR_DB: f32 = -120.0;
+
+// Everything the editor needs to draw one update, written by the background analysis task and read
+// by the editor through a triple buffer. All fields are fixed-size so that publishing never allocates.
+#[derive(Debug, Clone)]
+pub struct AnalysisFrame {
+    // Sounding notes, lowest first. Only the first num_notes are valid.
+    pub notes: [DetectedNote; MAX_FRAME_NOTES],
+    pub num_notes: usize,
+
+    // Chord currently recognized by the chord tracker. None while no decision has been made yet.
+    pub chord: Option<Option<Chord>>,
+
+    // Chords per bar, if the host provides a transport
+    pub progression: ChordGrid,
+
+    // Peak magnitude per log-spaced frequency bin in dBFS
+    pub spectrum: [f32; SPECTRUM_SNAPSHOT_BINS],
+
+    // Newest spectrogram column, in the same bins as the spectrum but from a much shorter window,
+    // and the number of columns computed so far. The editor adds a column whenever the number
+    // changes.
+    pub spectrogram_column: [f32; SPECTRUM_SNAPSHOT_BINS],
+    pub spectrogram_index: u64,
+
+    // Stage timings of the most recent FFT analysis frame
+    pub frame_timings: FrameTimings,
+
+    // Frequency range covered by the spectrum and the spectrogram, in Hz
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+
+    // Input levels of the most recent buffer in dBFS
+    pub peak_level_db: f32,
+    pub rms_level_db: f32,
+
+    // Host configuration, shown in the debug area
+    pub sample_rate: f32,
+    pub buffer_size: usize,
+}
+
+impl Default for AnalysisFrame {
+    fn default() -> Self {
+        Self {
+            notes: [DetectedNote::default(); MAX_FRAME_NOTES],
+            num_notes: 0,
+            chord: None,
+            progression: ChordGrid::new(1),
+            spectrum: [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS],
+            spectrogram_column: [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS],
+            spectrogram_index: 0,
+            frame_timings: FrameTimings::default(),
+            min_frequency: 20.0,
+            max_frequency: 20000.0,
+            peak_level_db: FLOOR_DB,
+            rms_level_db: FLOOR_DB,
+            sample_rate: 0.0,
+            buffer_size: 0,
+        }
+    }
+}
+
+// How long the stages of the most recent FFT analysis frame took, in milliseconds, and the average
+// and worst total over every frame since the analysis was initialized. The pitch estimator runs on
+// its own schedule, so it is timed separately.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTimings {
+    pub window_ms: f32,
+    pub fft_ms: f32,
+    pub magnitude_ms: f32,
+
+    // Display snapshot, chroma and chord tracking
+    pub chroma_ms: f32,
+
+    pub total_ms: f32,
+    pub average_total_ms: f32,
+    pub max_total_ms: f32,
+    pub frames: u64,
+
+    // Most recent frame of the current pitch estimator, and the average and worst since it was
+    // selected
+    pub estimator_ms: f32,
+    pub average_estimator_ms: f32,
+    pub max_estimator_ms: f32,
+    pub estimator_frames: u64,
+}
+
+impl FrameTimings {
+    pub fn record(&mut self, window_ms: f32, fft_ms: f32, magnitude_ms: f32, chroma_ms: f32) {
+        self.window_ms = window_ms;
+        self.fft_ms = fft_ms;
+        self.magnitude_ms = magnitude_ms;
+        self.chroma_ms = chroma_ms;
+        self.total_ms = window_ms + fft_ms + magnitude_ms + chroma_ms;
+
+        self.frames += 1;
+        self.average_total_ms += (self.total_ms - self.average_total_ms) / self.frames as f32;
+        self.max_total_ms = self.max_total_ms.max(self.total_ms);
+    }
+
+    pub fn record_estimator(&mut self, estimator_ms: f32) {
+        self.estimator_ms = estimator_ms;
+        self.estimator_frames += 1;
+        self.average_estimator_ms += (estimator_ms - self.average_estimator_ms) / self.estimator_frames as f32;
+        self.max_estimator_ms = self.max_estimator_ms.max(estimator_ms);
+    }
+
+    // Starts the estimator statistics over, e.g. when another estimator is selected
+    pub fn clear_estimator(&mut self) {
+        self.estimator_ms = 0.0;
+        self.average_estimator_ms = 0.0;
+        self.max_estimator_ms = 0.0;
+        self.estimator_frames = 0;
+    }
+}
+
+impl AnalysisFrame {
+    pub fn notes(&self) -> &[DetectedNote] {
+        &self.notes[..self.num_notes]
+    }
+
+    // Replaces the notes, dropping any beyond MAX_FRAME_NOTES
+    pub fn set_notes(&mut self, notes: impl Iterator<Item = DetectedNote>) {
+        self.num_notes = 0;
+        for note in notes.take(MAX_FRAME_NOTES) {
+            self.notes[self.num_notes] = note;
+            self.num_notes += 1;
+        }
+    }
+}
+
+// Centre frequency of a snapshot bin covering min_frequency to max_frequency
+pub fn snapshot_bin_frequency(bin: usize, min_frequency: f32, max_frequency: f32) -> f32 {
+    let position = bin as f32 / (SPECTRUM_SNAPSHOT_BINS - 1) as f32;
+    min_frequency * (max_frequency / min_frequency).powf(position)
+}
+
+// Reduces the part of the spectrum between min_frequency and max_frequency to
+// SPECTRUM_SNAPSHOT_BINS log-spaced bins. Each bin keeps the loudest spectrum bin that falls into
+// it, converted to dBFS using the magnitude of a full-scale sine. The loudest power is found first,
+// so that only the snapshot bins are converted to decibels.
+pub fn write_spectrum_snapshot(spectrum: &[SpectrumData],
+                               full_scale: f32,
+                               min_frequency: f32,
+                               max_frequency: f32,
+                               snapshot: &mut [f32; SPECTRUM_SNAPSHOT_BINS]) {
+    snapshot.iter_mut().for_each(|value| *value = FLOOR_DB);
+
+    let octaves = (max_frequency / min_frequency).log2();
+    if octaves.is_nan() || octaves <= 0.0 {
+        return;
+    }
+
+    let mut power = [0.0f32; SPECTRUM_SNAPSHOT_BINS];
+
+    for bin in spectrum {
+        if bin.frequency < min_frequency || bin.frequency > max_frequency {
+            continue;
+        }
+
+        let position = (bin.frequency / min_frequency).log2() / octaves;
+        let index = ((position * (SPECTRUM_SNAPSHOT_BINS - 1) as f32).round() as usize).min(SPECTRUM_SNAPSHOT_BINS - 1);
+        power[index] = power[index].max(bin.power);
+    }
+
+    let full_scale_power = full_scale * full_scale;
+    for (level_db, &power) in snapshot.iter_mut().zip(power.iter()) {
+        *level_db = 10.0 * (power / full_scale_power).max(1e-12).log10();
+    }
+}
+
+// Peak and RMS level of a block of samples in dBFS
+pub fn block_levels(samples: impl Iterator<Item = f32>) -> (f32, f32) {
+    let mut peak: f32 = 0.0;
+    let mut sum_of_squares = 0.0;
+    let mut count = 0;
+
+    for sample in samples {
+        peak = peak.max(sample.abs());
+        sum_of_squares += sample * sample;
+        count += 1;
+    }
+
+    let rms = (sum_of_squares / count.max(1) as f32).sqrt();
+    let to_db = |value: f32| (20.0 * value.max(1e-6).log10()).max(FLOOR_DB);
+
+    (to_db(peak), to_db(rms))
+}
@@ -0,0 +1,1163 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::Instant;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use crate::{DetectionMode, OnsetMethod, SpectrumData};
+use crate::analysis_frame::{write_spectrum_snapshot, AnalysisFrame, FrameTimings, FLOOR_DB, SPECTRUM_SNAPSHOT_BINS};
+use crate::beat_tracking::ChordGrid;
+use crate::chord_recognition::{ChordRecognizerConfig, ChordSegment, OnlineChordTracker};
+use crate::chroma::{compute_chroma, estimate_tuning, fold_to_semitones, ChromaConfig};
+use crate::error::{check_sample_rate, AnalysisError};
+use crate::hpss::{HarmonicSeparator, HpssConfig};
+use crate::logging::{LogCategory, LogLevel, Logger};
+use crate::note_detection::{frequency_to_note, DetectedNote};
+use crate::note_tracking::{NoteTracker, NoteTrackerConfig, NUM_PITCHES, SILENCE_DB};
+use crate::onset_detection::{Onset, OnsetConfig, OnsetDetector};
+use crate::pitch_estimation::{default_estimators, PitchCandidate, PitchEstimator};
+use crate::sanitize::sanitize_sample;
+
+// Analysis frame length in milliseconds, 65533 samples at 44.1 kHz. Testing showed this to be a
+// good balance between performance and algorithm accuracy. The FFT peak estimator uses the same
+// length.
+pub const WINDOW_LENGTH_MS: f32 = 1486.0;
+
+// Spacing of the analysis FFT bins in Hz. Frames are zero-padded to the FFT size that gives this
+// spacing, 88200 points at 44.1 kHz and 96000 at 48 kHz, so that peaks are resolved the same at
+// every sample rate. The sizes of the common rates factor into small primes, which keeps the FFT
+// about as fast as a power of two.
+pub const FFT_BIN_WIDTH_HZ: f32 = 0.5;
+
+// Frame length of the spectrogram columns in milliseconds, 8189 samples zero-padded to an 8192
+// point FFT at 44.1 kHz. Much shorter than the analysis window so that the display follows the
+// music, yet still long enough to tell neighbouring notes apart from about 100 Hz up.
+const SPECTROGRAM_WINDOW_MS: f32 = 185.7;
+
+// Number of recent onsets kept for the analysis API
+const MAX_ONSET_HISTORY: usize = 64;
+
+// Number of finished chord segments kept for the analysis API
+const MAX_CHORD_HISTORY: usize = 256;
+
+// Most pitch candidates a single estimate can report
+const MAX_CANDIDATES: usize = 512;
+
+// Number of bars shown in the chord progression
+const MAX_PROGRESSION_BARS: usize = 4;
+
+// FFT size for a frame of window_size samples: the even size closest to FFT_BIN_WIDTH_HZ bins, or
+// the frame itself if that is longer
+pub fn analysis_fft_size(sample_rate: f32, window_size: usize) -> usize {
+    let size = (sample_rate / FFT_BIN_WIDTH_HZ / 2.0).round() as usize * 2;
+    size.max(window_size + window_size % 2)
+}
+
+// Distance between the level that starts a note and the lower level that releases it, in dB
+const NOTE_THRESHOLD_HYSTERESIS_DB: f32 = 12.0;
+
+// Values handled per iteration by the windowing, magnitude and level loops. Fixed-size chunks
+// without bounds checks let the compiler vectorize them.
+pub const CHUNK_SIZE: usize = 8;
+
+// Work sent from the audio thread to the background analysis task
+#[derive(Debug, Clone, Copy)]
+pub enum AnalysisTask {
+    // Analyse every sample that has been queued so far
+    Analyze(BlockInfo),
+
+    // Drop all analysis state, e.g. after the transport was stopped and restarted
+    Reset,
+
+    // Forget the chord history and progression, e.g. when asked to from the editor
+    ClearHistory,
+}
+
+// What the audio thread knows about the most recent block of samples
+#[derive(Debug, Clone, Copy)]
+pub struct BlockInfo {
+    // Host transport position at the end of the queued samples, in beats, if the host is playing
+    pub position_beats: Option<f64>,
+
+    // Host tempo in beats per minute, if known
+    pub tempo: Option<f64>,
+
+    // Time signature numerator reported by the host
+    pub beats_per_bar: usize,
+
+    // Input levels of the most recent buffer in dBFS
+    pub peak_level_db: f32,
+    pub rms_level_db: f32,
+
+    // Size of the most recent buffer in samples
+    pub buffer_size: usize,
+
+    // How notes are detected
+    pub mode: DetectionMode,
+
+    // Frequency range analysed, in Hz
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+
+    // Level a pitch has to reach to start a note, in dBFS
+    pub note_threshold_db: f32,
+
+    // Whether drums and other percussive sounds are filtered out before detection
+    pub suppress_percussion: bool,
+
+    // Detection function of the onset detector
+    pub onset_method: OnsetMethod,
+}
+
+// All analysis state. Lives behind a mutex that only the background task locks, and is fed with
+// samples through a lock-free queue written by the audio thread. Every buffer is allocated up
+// front, so running an analysis never allocates.
+pub struct Analyzer {
+    // Need to get upon initialization for proper windowing and FFT
+    sample_rate: f32,
+
+    // Read side of the sample queue filled by the audio thread
+    sample_consumer: rtrb::Consumer<f32>,
+
+    // Analysis frame length in samples at the current sample rate, and the FFT size the frame is
+    // zero-padded to
+    window_size: usize,
+    fft_size: usize,
+
+    // Vector of complex buffer values
+    complex_buffer: Vec<Complex<f32>>,
+
+    // The most recent window_size samples in order, copied from the sample history for every FFT
+    // frame, and the samples left until the next frame
+    sample_vec: Vec<f32>,
+    samples_until_frame: usize,
+
+    // Vector for windowed values
+    windowed_values: Vec<f32>,
+
+    // Hann window coefficients for the window size, computed once in initialize
+    window: Vec<f32>,
+
+    // FFT algorithm object
+    fft_algorithm: Arc<dyn Fft<f32>>,
+
+    // Spectrum data object. Bin frequencies are set in initialize; each frame only updates the
+    // squared magnitudes.
+    spectrum_data: Vec<SpectrumData>,
+
+    // Spacing of the FFT bins in Hz. Equal to: Sample Rate / FFT Size
+    frequency_resolution: f32,
+
+    // Number of bins below the Nyquist frequency. Equal to: FFT Size / 2
+    nyquist_limit: usize,
+
+    // Level of every MIDI pitch in the most recent frame, in dBFS
+    pitch_levels: [f32; NUM_PITCHES],
+
+    // Most recent measurement of every pitch. Kept while a note releases, so that it can still be
+    // reported after its peak has gone.
+    pitch_notes: [DetectedNote; NUM_PITCHES],
+
+    // Per-pitch note state driven by the pitch levels, and the level that starts a note
+    note_tracker: NoteTracker,
+    note_threshold_db: f32,
+
+    // Settings for the chroma (pitch-class profile) stage
+    chroma_config: ChromaConfig,
+
+    // Chroma vector of the most recent frame, one value per pitch-class bin starting at C
+    chroma: Vec<f32>,
+
+    // Estimated tuning deviation from A4 = 440 Hz, in semitones
+    tuning_offset: f32,
+
+    // HMM-smoothed chord tracker fed with one chroma frame per analysis
+    chord_tracker: OnlineChordTracker,
+
+    // Most recent chord segments that have finished, oldest first
+    chord_history: Vec<ChordSegment>,
+
+    // Chords quantised to the host's beats and bars
+    chord_grid: ChordGrid,
+
+    // Current detection mode, which selects the pitch estimator
+    mode: DetectionMode,
+
+    // Frequency range notes are detected and the spectrum is shown in, in Hz
+    min_frequency: f32,
+    max_frequency: f32,
+
+    // One pitch estimator per detection mode, in the order of DetectionMode. All are created up
+    // front so that switching modes does not allocate.
+    estimators: Vec<Box<dyn PitchEstimator>>,
+
+    // The most recent samples, as a circular buffer long enough for every estimator's frame
+    estimator_history: Vec<f32>,
+    estimator_history_position: usize,
+
+    // The current estimator's frame in order, and the samples left until its next frame
+    estimator_frame: Vec<f32>,
+    samples_until_estimate: usize,
+
+    // Pitches reported by the most recent estimate
+    candidates: Vec<PitchCandidate>,
+
+    // Median-filtering stage that removes percussive energy from the incoming audio when enabled.
+    // Everything after it, onsets included, then sees only the harmonic part, delayed by the
+    // separator's latency.
+    harmonic_separator: HarmonicSeparator,
+    suppress_percussion: bool,
+
+    // Onset detector running on short overlapping frames of the incoming audio
+    onset_detector: OnsetDetector,
+
+    // Most recent onsets, oldest first
+    onsets: Vec<Onset>,
+
+    // Number of samples that have been analysed, used to timestamp analysis frames
+    samples_processed: u64,
+
+    // Spectrum of the most recent frame reduced to log-spaced bins for display
+    spectrum_snapshot: [f32; SPECTRUM_SNAPSHOT_BINS],
+
+    // How long the stages of the FFT analysis took, for the diagnostics panel
+    frame_timings: FrameTimings,
+
+    // Short-time spectrum of the most recent samples for the editor's spectrogram, recomputed
+    // every time the task runs, and the number of columns computed so far
+    spectrogram_fft: Arc<dyn Fft<f32>>,
+    spectrogram_window: Vec<f32>,
+    spectrogram_buffer: Vec<Complex<f32>>,
+    spectrogram_data: Vec<SpectrumData>,
+    spectrogram_column: [f32; SPECTRUM_SNAPSHOT_BINS],
+    spectrogram_index: u64,
+
+    // Write side of the triple buffer that hands analysis results to the editor
+    analysis_input: triple_buffer::Input<AnalysisFrame>,
+
+    // Log queue of the background thread
+    logger: Logger,
+}
+
+impl Analyzer {
+    pub fn new(sample_consumer: rtrb::Consumer<f32>,
+               analysis_input: triple_buffer::Input<AnalysisFrame>,
+               logger: Logger) -> Self {
+        // The FFT and its buffers are sized in initialize once the sample rate is known
+        Self {
+            // Initialize sample rate to standard of 44.1khz. Will be updated in initialize function.
+            sample_rate: 44100.0,
+
+            sample_consumer,
+
+            window_size: 0,
+            fft_size: 0,
+            complex_buffer: Vec::new(),
+            sample_vec: Vec::new(),
+            samples_until_frame: 0,
+            windowed_values: Vec::new(),
+            window: Vec::new(),
+            fft_algorithm: FftPlanner::new().plan_fft_forward(0),
+            spectrum_data: Vec::new(),
+            frequency_resolution: 0.0,
+            nyquist_limit: 0,
+
+            // Note tracking
+            pitch_levels: [SILENCE_DB; NUM_PITCHES],
+            pitch_notes: [DetectedNote::default(); NUM_PITCHES],
+            note_tracker: NoteTracker::new(NoteTrackerConfig::default()),
+            note_threshold_db: NoteTrackerConfig::default().on_threshold_db,
+
+            // Chroma defaults to 12 bins; resized in initialize if the config asks for more
+            chroma_config: ChromaConfig::default(),
+            chroma: vec![0.0; 12],
+            tuning_offset: 0.0,
+
+            // Chord tracking
+            chord_tracker: OnlineChordTracker::new(ChordRecognizerConfig::default()),
+            chord_history: Vec::with_capacity(MAX_CHORD_HISTORY),
+            chord_grid: ChordGrid::new(MAX_PROGRESSION_BARS),
+
+            // Pitch estimation. The history is sized in initialize once the sample rate is known.
+            mode: DetectionMode::Polyphonic,
+            min_frequency: 20.0,
+            max_frequency: 20000.0,
+            estimators: default_estimators(),
+            estimator_history: Vec::new(),
+            estimator_history_position: 0,
+            estimator_frame: Vec::new(),
+            samples_until_estimate: 0,
+            candidates: Vec::with_capacity(MAX_CANDIDATES),
+
+            // Percussion filter. Recreated in initialize once the sample rate is known.
+            harmonic_separator: HarmonicSeparator::new(HpssConfig::default(), 44100.0),
+            suppress_percussion: false,
+
+            // Onset detection. Recreated in initialize once the sample rate is known.
+            onset_detector: OnsetDetector::new(OnsetConfig::default(), 44100.0),
+            onsets: Vec::with_capacity(MAX_ONSET_HISTORY),
+            samples_processed: 0,
+
+            // Editor channel. The spectrogram FFT is sized in initialize like the main one.
+            spectrum_snapshot: [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS],
+            frame_timings: FrameTimings::default(),
+            spectrogram_fft: FftPlanner::new().plan_fft_forward(0),
+            spectrogram_window: Vec::new(),
+            spectrogram_buffer: Vec::new(),
+            spectrogram_data: Vec::new(),
+            spectrogram_column: [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS],
+            spectrogram_index: 0,
+            analysis_input,
+            logger,
+        }
+    }
+
+    // Prepares the analysis for the host's sample rate and a freshly created sample queue.
+    // Called from the plugin's initialize function whenever the sample rate or channel layout
+    // changes, never from the audio thread. Rebuilds everything that depends on the sample rate.
+    // An invalid sample rate is rejected before anything is changed.
+    pub fn initialize(&mut self, sample_rate: f32, sample_consumer: rtrb::Consumer<f32>) -> Result<(), AnalysisError> {
+        check_sample_rate(sample_rate)?;
+
+        // Set sample rate
+        self.sample_rate = sample_rate;
+        self.sample_consumer = sample_consumer;
+
+        // FFT plan, buffers and bin frequencies for the window length at this sample rate. Only the
+        // first window_size windowed values are ever written, so the rest stay zero as padding.
+        self.window_size = ((WINDOW_LENGTH_MS * sample_rate / 1000.0).round() as usize).max(2);
+        self.fft_size = analysis_fft_size(sample_rate, self.window_size);
+        self.fft_algorithm = FftPlanner::new().plan_fft_forward(self.fft_size);
+        self.complex_buffer = vec![Complex { re: 0.0, im: 0.0 }; self.fft_size];
+        self.sample_vec = Vec::with_capacity(self.window_size);
+        self.windowed_values = vec![0.0; self.fft_size];
+        self.window = (0..self.window_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (self.window_size as f32 - 1.0)).cos())
+            .collect();
+
+        self.frequency_resolution = sample_rate / self.fft_size as f32;
+        self.nyquist_limit = self.fft_size / 2;
+        self.spectrum_data = (0..self.nyquist_limit)
+            .map(|i| SpectrumData { frequency: i as f32 * self.frequency_resolution, power: 0.0 })
+            .collect();
+
+        self.chroma.resize(self.chroma_config.bins_per_octave, 0.0);
+        self.frame_timings = FrameTimings::default();
+
+        self.harmonic_separator = HarmonicSeparator::new(HpssConfig::default(), self.sample_rate);
+        self.onset_detector = OnsetDetector::new(self.onset_detector.config().clone(), self.sample_rate);
+
+        let (min_frequency, max_frequency) = (self.min_frequency, self.max_frequency);
+        set_frequency_range(self, min_frequency, max_frequency);
+
+        // Size the sample history for the longest frame, the FFT frame included, and let every
+        // estimator see one frame so that it can set up its buffers for this sample rate
+        let longest_frame = self.estimators
+            .iter()
+            .map(|estimator| estimator.frame_size(self.sample_rate))
+            .fold(self.window_size, usize::max);
+        self.estimator_history = vec![0.0; longest_frame];
+        self.estimator_history_position = 0;
+        self.estimator_frame = Vec::with_capacity(longest_frame);
+
+        // The spectrogram reads its samples from the same history
+        let spectrogram_size = ((SPECTROGRAM_WINDOW_MS * sample_rate / 1000.0).round() as usize).clamp(2, longest_frame.max(2));
+        let spectrogram_fft_size = spectrogram_size.next_power_of_two();
+        self.spectrogram_fft = FftPlanner::new().plan_fft_forward(spectrogram_fft_size);
+        self.spectrogram_window = (0..spectrogram_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / spectrogram_size as f32).cos())
+            .collect();
+        self.spectrogram_buffer = vec![Complex { re: 0.0, im: 0.0 }; spectrogram_fft_size];
+        self.spectrogram_data = (0..spectrogram_fft_size / 2)
+            .map(|i| SpectrumData { frequency: i as f32 * sample_rate / spectrogram_fft_size as f32, power: 0.0 })
+            .collect();
+
+        for estimator in self.estimators.iter_mut() {
+            if estimator.uses_spectrum() {
+                estimator.estimate_spectrum(&self.spectrum_data, sample_rate, self.window_size, &mut self.candidates)?;
+            } else {
+                self.estimator_frame.clear();
+                self.estimator_frame.resize(estimator.frame_size(sample_rate), 0.0);
+                estimator.estimate(&self.estimator_frame, sample_rate, &mut self.candidates)?;
+            }
+        }
+        self.candidates.clear();
+
+        self.logger.log(LogLevel::Info, LogCategory::Buffers, format_args!(
+            "Analysis prepared for {} Hz: {} samples in a {} point FFT, spectrogram of {} samples in a {} point FFT, history of {} samples",
+            sample_rate, self.window_size, self.fft_size, spectrogram_size, spectrogram_fft_size, longest_frame));
+
+        self.reset();
+        Ok(())
+    }
+
+    // Clears every piece of state that depends on the audio heard so far, without reallocating.
+    // Runs on the background thread when the host resets the plugin.
+    pub fn reset(&mut self) {
+        self.sample_vec.clear();
+        self.samples_until_frame = self.window_size;
+        self.samples_processed = 0;
+
+        self.estimator_history.iter_mut().for_each(|sample| *sample = 0.0);
+        self.estimator_history_position = 0;
+        self.samples_until_estimate = self.estimators[estimator_index(self.mode)].frame_size(self.sample_rate);
+        self.candidates.clear();
+        self.estimators.iter_mut().for_each(|estimator| estimator.reset());
+
+        self.pitch_levels = [SILENCE_DB; NUM_PITCHES];
+        self.pitch_notes = [DetectedNote::default(); NUM_PITCHES];
+        self.note_tracker.reset();
+
+        self.chroma.iter_mut().for_each(|value| *value = 0.0);
+        self.tuning_offset = 0.0;
+        self.chord_tracker.reset();
+        self.chord_history.clear();
+        self.chord_grid.reset();
+
+        self.harmonic_separator.reset();
+        self.onset_detector.reset();
+        self.onsets.clear();
+
+        self.spectrum_snapshot = [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS];
+        self.spectrogram_column = [FLOOR_DB; SPECTRUM_SNAPSHOT_BINS];
+    }
+
+    // Replaces the chord recognizer settings, e.g. to match chroma against binary instead of
+    // harmonic templates. The chord tracker starts over, so the current chord is dropped.
+    pub fn set_chord_config(&mut self, config: ChordRecognizerConfig) {
+        self.chord_tracker = OnlineChordTracker::new(config);
+    }
+
+    // Most recent onsets with their times in seconds since the last reset and their strengths,
+    // oldest first. Holds at most the last MAX_ONSET_HISTORY onsets.
+    pub fn onsets(&self) -> &[Onset] {
+        &self.onsets
+    }
+
+    // Finished chord segments with their start and end times in seconds since the last reset,
+    // oldest first. Holds at most the last MAX_CHORD_HISTORY segments.
+    pub fn chord_segments(&self) -> &[ChordSegment] {
+        &self.chord_history
+    }
+
+    // Drops the finished chord segments and the chord progression. The current chord and the
+    // notes are kept.
+    pub fn clear_history(&mut self) {
+        self.chord_history.clear();
+        self.chord_grid.reset();
+    }
+
+    // Runs on the background thread. Analyses every queued sample, then publishes the results.
+    // Problems with single frames are logged and the frame is skipped; only an analysis that
+    // cannot run at all returns an error.
+    pub fn run(&mut self, task: AnalysisTask) -> Result<(), AnalysisError> {
+        let block_info = match task {
+            AnalysisTask::Analyze(block_info) => block_info,
+            AnalysisTask::Reset => {
+                self.reset();
+                return Ok(());
+            }
+            AnalysisTask::ClearHistory => {
+                self.clear_history();
+                return Ok(());
+            }
+        };
+
+        if self.window_size == 0 {
+            return Err(AnalysisError::NotInitialized);
+        }
+
+        let started = Instant::now();
+        let samples_before = self.samples_processed;
+
+        if block_info.mode != self.mode {
+            set_mode(self, block_info.mode);
+            self.logger.log(LogLevel::Info, LogCategory::Thresholds, format_args!("Detection mode changed to {:?}", self.mode));
+        }
+
+        if block_info.min_frequency != self.min_frequency || block_info.max_frequency != self.max_frequency {
+            set_frequency_range(self, block_info.min_frequency, block_info.max_frequency);
+            self.logger.log(LogLevel::Info, LogCategory::Thresholds, format_args!(
+                "Frequency range changed to {:.1} Hz to {:.1} Hz", self.min_frequency, self.max_frequency));
+        }
+
+        if block_info.note_threshold_db != self.note_threshold_db {
+            self.note_threshold_db = block_info.note_threshold_db;
+            self.note_tracker.set_thresholds(self.note_threshold_db, self.note_threshold_db - NOTE_THRESHOLD_HYSTERESIS_DB);
+            self.logger.log(LogLevel::Info, LogCategory::Thresholds, format_args!(
+                "Note threshold changed to {:.1} dBFS, released below {:.1} dBFS",
+                self.note_threshold_db, self.note_threshold_db - NOTE_THRESHOLD_HYSTERESIS_DB));
+        }
+
+        // Start the filter from silence, so that it does not release audio from when it was last on
+        if block_info.suppress_percussion != self.suppress_percussion {
+            self.suppress_percussion = block_info.suppress_percussion;
+            self.harmonic_separator.reset();
+            self.logger.log(LogLevel::Info, LogCategory::Thresholds, format_args!(
+                "Percussion suppression {}", if self.suppress_percussion { "enabled" } else { "disabled" }));
+        }
+
+        if block_info.onset_method != self.onset_detector.config().method {
+            self.onset_detector.set_method(block_info.onset_method);
+            self.logger.log(LogLevel::Info, LogCategory::Thresholds, format_args!(
+                "Onset method changed to {:?}", block_info.onset_method));
+        }
+
+        let mut invalid_samples = 0;
+        while let Ok(sample) = self.sample_consumer.pop() {
+            self.samples_processed += 1;
+
+            // NaN and infinite samples would spread through every later stage, so they are
+            // analysed as silence
+            if !sample.is_finite() {
+                invalid_samples += 1;
+            }
+            let sample = sanitize_sample(sample);
+
+            let sample = if self.suppress_percussion {
+                self.harmonic_separator.process_sample(sample)
+            } else {
+                sample
+            };
+
+            // Onset detection sees every sample, including quiet ones, so that it can find
+            // the attack that follows silence
+            if let Some(onset) = self.onset_detector.process_sample(sample) {
+                register_onset(self, onset);
+            }
+
+            // The sample history feeds the pitch estimators and the FFT frames
+            push_estimator_sample(self, sample);
+
+            // Every window_size samples, the most recent ones are analysed as one FFT frame
+            self.samples_until_frame = self.samples_until_frame.saturating_sub(1);
+            if self.samples_until_frame == 0 {
+                self.samples_until_frame = self.window_size;
+                match perform_analysis(self) {
+                    Ok(()) => {
+                        let timings = self.frame_timings;
+                        self.logger.log(LogLevel::Debug, LogCategory::Timing, format_args!(
+                            "FFT analysis frame took {:.2} ms: window {:.2}, FFT {:.2}, magnitudes {:.2}, chroma and chords {:.2}",
+                            timings.total_ms, timings.window_ms, timings.fft_ms, timings.magnitude_ms, timings.chroma_ms));
+                    }
+                    Err(error) => self.logger.log(LogLevel::Error, LogCategory::Input, format_args!(
+                        "FFT analysis frame skipped: {}", error)),
+                }
+            }
+        }
+
+        // One spectrogram column per run, showing the signal the estimators saw
+        compute_spectrogram_column(self);
+
+        if invalid_samples > 0 {
+            self.logger.log(LogLevel::Warning, LogCategory::Input, format_args!(
+                "{} NaN or infinite samples were analysed as silence", invalid_samples));
+        }
+
+        // Quantise the current chord to the host's beat grid. The tracker decides a frame only after
+        // its lag, so the chord is placed at the transport position where that frame's audio ended.
+        let decision = self.chord_tracker.decided_frame_time();
+        if let (Some(position_beats), Some(tempo), Some(frame_time)) = (block_info.position_beats, block_info.tempo, decision) {
+            let chord = self.chord_tracker.current_segment().and_then(|segment| segment.chord);
+            let latency = self.samples_processed as f64 / self.sample_rate as f64 - frame_time as f64;
+            self.chord_grid.record_delayed(position_beats, tempo, latency, block_info.beats_per_bar, chord);
+        }
+
+        // Publish the results for the editor. Text formatting happens in the editor.
+        let frame = self.analysis_input.input_buffer();
+        let (note_tracker, pitch_notes) = (&self.note_tracker, &self.pitch_notes);
+        frame.set_notes(note_tracker.sounding_notes().map(|midi_note| DetectedNote {
+            first_seen: note_tracker.onset_time(midi_note),
+            ..pitch_notes[midi_note]
+        }));
+        frame.chord = self.chord_tracker.current_segment().map(|segment| segment.chord);
+        frame.progression = self.chord_grid;
+        frame.spectrum = self.spectrum_snapshot;
+        frame.spectrogram_column = self.spectrogram_column;
+        frame.spectrogram_index = self.spectrogram_index;
+        frame.frame_timings = self.frame_timings;
+        frame.min_frequency = self.min_frequency;
+        frame.max_frequency = self.max_frequency;
+        frame.peak_level_db = block_info.peak_level_db;
+        frame.rms_level_db = block_info.rms_level_db;
+        frame.sample_rate = self.sample_rate;
+        frame.buffer_size = block_info.buffer_size;
+        self.analysis_input.publish();
+
+        // The analysis falls behind if it needs longer than the audio it analysed lasts
+        let elapsed = started.elapsed().as_secs_f64();
+        let audio_duration = (self.samples_processed - samples_before) as f64 / self.sample_rate as f64;
+        if elapsed > audio_duration && audio_duration > 0.0 {
+            self.logger.log(LogLevel::Warning, LogCategory::Timing, format_args!(
+                "Analysis is slower than real time: {:.1} ms for {:.1} ms of audio",
+                elapsed * 1000.0, audio_duration * 1000.0));
+        }
+
+        Ok(())
+    }
+}
+
+// Index into Analyzer::estimators for a detection mode
+fn estimator_index(mode: DetectionMode) -> usize {
+    match mode {
+        DetectionMode::Polyphonic => 0,
+        DetectionMode::Yin => 1,
+        DetectionMode::Mpm => 2,
+    }
+}
+
+// Switches to another pitch estimator. Notes found by the previous one are dropped, and the new
+// one starts as soon as the history holds a full frame.
+fn set_mode(analyzer: &mut Analyzer, mode: DetectionMode) {
+    analyzer.mode = mode;
+    analyzer.note_tracker.reset();
+    analyzer.frame_timings.clear_estimator();
+
+    let estimator = &analyzer.estimators[estimator_index(mode)];
+    analyzer.samples_until_estimate = estimator.hop_size(analyzer.sample_rate);
+}
+
+// Limits note detection and the spectrum display to a frequency range. The bounds are swapped if
+// they arrive in the wrong order. Notes outside the new range are released by the tracker.
+fn set_frequency_range(analyzer: &mut Analyzer, min_frequency: f32, max_frequency: f32) {
+    analyzer.min_frequency = min_frequency.min(max_frequency);
+    analyzer.max_frequency = max_frequency.max(min_frequency);
+
+    for estimator in analyzer.estimators.iter_mut() {
+        estimator.set_frequency_range(analyzer.min_frequency, analyzer.max_frequency);
+    }
+}
+
+// Adds one sample to the estimator history and runs the current estimator whenever its next frame
+// is complete
+fn push_estimator_sample(analyzer: &mut Analyzer, sample: f32) {
+    let history_size = analyzer.estimator_history.len();
+    if history_size == 0 {
+        return;
+    }
+
+    analyzer.estimator_history[analyzer.estimator_history_position] = sample;
+    analyzer.estimator_history_position = (analyzer.estimator_history_position + 1) % history_size;
+
+    // Estimators that work on spectra are run with the FFT frames instead
+    if analyzer.estimators[estimator_index(analyzer.mode)].uses_spectrum() {
+        return;
+    }
+
+    analyzer.samples_until_estimate = analyzer.samples_until_estimate.saturating_sub(1);
+    if analyzer.samples_until_estimate > 0 {
+        return;
+    }
+
+    let sample_rate = analyzer.sample_rate;
+    let estimator = &mut analyzer.estimators[estimator_index(analyzer.mode)];
+    let frame_size = estimator.frame_size(sample_rate).min(history_size);
+    let hop_size = estimator.hop_size(sample_rate).max(1);
+    analyzer.samples_until_estimate = hop_size;
+
+    read_history(&analyzer.estimator_history, analyzer.estimator_history_position, frame_size, &mut analyzer.estimator_frame);
+
+    let started = Instant::now();
+    let result = estimator.estimate(&analyzer.estimator_frame, sample_rate, &mut analyzer.candidates);
+    analyzer.frame_timings.record_estimator(started.elapsed().as_secs_f32() * 1000.0);
+
+    if let Err(error) = result {
+        let name = estimator.name();
+        analyzer.logger.log(LogLevel::Error, LogCategory::Input, format_args!("{} frame skipped: {}", name, error));
+        return;
+    }
+
+    identify_notes(analyzer, hop_size as f32 / sample_rate);
+}
+
+// Copies the most recent `count` samples of the circular history into `frame`, oldest first. The
+// position points at the oldest sample, so the newest samples end just before it.
+fn read_history(history: &[f32], position: usize, count: usize, frame: &mut Vec<f32>) {
+    let count = count.min(history.len());
+    let start = (position + history.len() - count) % history.len().max(1);
+
+    frame.clear();
+    if start + count <= history.len() {
+        frame.extend_from_slice(&history[start..start + count]);
+    } else {
+        frame.extend_from_slice(&history[start..]);
+        frame.extend_from_slice(&history[..start + count - history.len()]);
+    }
+}
+
+// Computes the spectrum of the most recent samples in the estimator history and reduces it to a
+// spectrogram column covering the current frequency range
+fn compute_spectrogram_column(analyzer: &mut Analyzer) {
+    let history_size = analyzer.estimator_history.len();
+    let size = analyzer.spectrogram_window.len();
+    if size == 0 || size > history_size {
+        return;
+    }
+
+    // The position points at the oldest sample, so the newest samples end just before it. The
+    // FFT overwrites its buffer, so the padding is cleared every time.
+    let start = analyzer.estimator_history_position + history_size - size;
+    let (frame, padding) = analyzer.spectrogram_buffer.split_at_mut(size);
+    for (i, (value, window)) in frame.iter_mut().zip(analyzer.spectrogram_window.iter()).enumerate() {
+        *value = Complex { re: analyzer.estimator_history[(start + i) % history_size] * window, im: 0.0 };
+    }
+    padding.iter_mut().for_each(|value| *value = Complex { re: 0.0, im: 0.0 });
+    analyzer.spectrogram_fft.process(&mut analyzer.spectrogram_buffer);
+
+    for (spectrum_data, complex) in analyzer.spectrogram_data.iter_mut().zip(analyzer.spectrogram_buffer.iter()) {
+        spectrum_data.power = complex.norm_sqr();
+    }
+
+    write_spectrum_snapshot(&analyzer.spectrogram_data,
+                            size as f32 / 4.0,
+                            analyzer.min_frequency,
+                            analyzer.max_frequency,
+                            &mut analyzer.spectrogram_column);
+    analyzer.spectrogram_index += 1;
+}
+
+// Records an onset and moves sounding notes to release, so that notes from before the attack are
+// dropped unless the next frames detect them again
+fn register_onset(analyzer: &mut Analyzer, onset: Onset) {
+    if analyzer.onsets.len() == MAX_ONSET_HISTORY {
+        analyzer.onsets.remove(0);
+    }
+    analyzer.onsets.push(onset);
+
+    let time = analyzer.samples_processed as f32 / analyzer.sample_rate;
+    analyzer.note_tracker.on_onset(time);
+}
+
+// Executes algorithm
+fn perform_analysis(analyzer: &mut Analyzer) -> Result<(), AnalysisError> {
+    let started = Instant::now();
+
+    // The frame is the most recent window_size samples of the history
+    read_history(&analyzer.estimator_history, analyzer.estimator_history_position, analyzer.window_size, &mut analyzer.sample_vec);
+
+    // Apply the window function to the audio data (Hanning, etc.)
+    apply_window_function(analyzer);
+    let windowed = Instant::now();
+
+    // Perform the FFT
+    perform_fft(analyzer)?;
+    let transformed = Instant::now();
+
+    // Get the spectrum data
+    get_spectrum_data(analyzer);
+    let measured = Instant::now();
+
+    // Reduce the spectrum for display
+    write_spectrum_snapshot(&analyzer.spectrum_data[..analyzer.nyquist_limit],
+                            analyzer.window_size as f32 / 4.0,
+                            analyzer.min_frequency,
+                            analyzer.max_frequency,
+                            &mut analyzer.spectrum_snapshot);
+
+    // Fold the spectrum into pitch classes
+    get_chroma(analyzer);
+
+    // Update the smoothed chord estimate
+    track_chords(analyzer);
+
+    let milliseconds = |from: Instant, to: Instant| (to - from).as_secs_f32() * 1000.0;
+    analyzer.frame_timings.record(milliseconds(started, windowed),
+                                  milliseconds(windowed, transformed),
+                                  milliseconds(transformed, measured),
+                                  milliseconds(measured, Instant::now()));
+
+    // The polyphonic estimator picks its peaks from the same spectrum
+    estimate_from_spectrum(analyzer);
+
+    Ok(())
+}
+
+// Runs the current estimator on the spectrum of the FFT frame, if it works on spectra, and
+// advances the note tracker by one frame
+fn estimate_from_spectrum(analyzer: &mut Analyzer) {
+    let estimator = &mut analyzer.estimators[estimator_index(analyzer.mode)];
+    if !estimator.uses_spectrum() {
+        return;
+    }
+
+    let started = Instant::now();
+    let result = estimator.estimate_spectrum(&analyzer.spectrum_data[..analyzer.nyquist_limit],
+                                             analyzer.sample_rate,
+                                             analyzer.window_size,
+                                             &mut analyzer.candidates);
+    analyzer.frame_timings.record_estimator(started.elapsed().as_secs_f32() * 1000.0);
+
+    if let Err(error) = result {
+        let name = estimator.name();
+        analyzer.logger.log(LogLevel::Error, LogCategory::Input, format_args!("{} frame skipped: {}", name, error));
+        return;
+    }
+
+    identify_notes(analyzer, analyzer.window_size as f32 / analyzer.sample_rate);
+}
+
+// Function Definitions
+fn perform_fft(analyzer: &mut Analyzer) -> Result<(), AnalysisError> {
+    // Check that the input and complex_vec are the same length
+    if analyzer.windowed_values.len() != analyzer.complex_buffer.len() {
+        return Err(AnalysisError::LengthMismatch {
+            expected: analyzer.complex_buffer.len(),
+            actual: analyzer.windowed_values.len(),
+        });
+    }
+
+    // Populate the complex_vec with values from windowed vec
+    for (complex, &input_val) in analyzer.complex_buffer
+        .iter_mut()
+        .zip(analyzer.windowed_values.iter()) {
+        *complex = Complex { re: input_val, im: 0.0 };
+    }
+
+    // Perform forward FFT on input data
+    analyzer.fft_algorithm.process(&mut analyzer.complex_buffer);
+
+    Ok(())
+}
+
+
+// Utility function to apply the cached window function
+fn apply_window_function(analyzer: &mut Analyzer) {
+    let length = analyzer.windowed_values.len().min(analyzer.sample_vec.len()).min(analyzer.window.len());
+    let samples = &analyzer.sample_vec[..length];
+    let window = &analyzer.window[..length];
+    let windowed_values = &mut analyzer.windowed_values[..length];
+
+    let mut sample_chunks = samples.chunks_exact(CHUNK_SIZE);
+    let mut window_chunks = window.chunks_exact(CHUNK_SIZE);
+    let mut windowed_chunks = windowed_values.chunks_exact_mut(CHUNK_SIZE);
+    for ((windowed, samples), window) in (&mut windowed_chunks).zip(&mut sample_chunks).zip(&mut window_chunks) {
+        for i in 0..CHUNK_SIZE {
+            windowed[i] = samples[i] * window[i];
+        }
+    }
+
+    for ((windowed, &sample), &window) in windowed_chunks.into_remainder().iter_mut()
+        .zip(sample_chunks.remainder())
+        .zip(window_chunks.remainder()) {
+        *windowed = sample * window;
+    }
+}
+
+// Transforms buffer of complex numbers from FFT forward transform into the squared magnitudes of
+// the SpectrumData bins. Levels and peaks compare the same either way, so the square root is left
+// to the few places that need linear magnitudes. Bin frequencies are fixed for a sample rate and
+// set in initialize.
+fn get_spectrum_data(analyzer: &mut Analyzer) {
+    let length = analyzer.nyquist_limit.min(analyzer.spectrum_data.len());
+    squared_magnitudes(&analyzer.complex_buffer, &mut analyzer.spectrum_data[..length]);
+}
+
+// Writes the squared magnitude of every FFT bin into the matching SpectrumData, for as many bins
+// as both hold. Shared with the FFT peak estimator when it runs its own FFT.
+pub fn squared_magnitudes(complex_buffer: &[Complex<f32>], spectrum_data: &mut [SpectrumData]) {
+    let length = spectrum_data.len().min(complex_buffer.len());
+    let spectrum_data = &mut spectrum_data[..length];
+    let complex_buffer = &complex_buffer[..length];
+
+    let mut data_chunks = spectrum_data.chunks_exact_mut(CHUNK_SIZE);
+    let mut complex_chunks = complex_buffer.chunks_exact(CHUNK_SIZE);
+    for (data, complex) in (&mut data_chunks).zip(&mut complex_chunks) {
+        for i in 0..CHUNK_SIZE {
+            data[i].power = complex[i].re * complex[i].re + complex[i].im * complex[i].im;
+        }
+    }
+
+    for (spectrum_data, complex) in data_chunks.into_remainder().iter_mut().zip(complex_chunks.remainder()) {
+        spectrum_data.power = complex.norm_sqr();
+    }
+}
+
+// Converts squared magnitudes to levels in dB relative to full_scale_power, for as many bins as
+// both hold. Levels stop at -120 dB, so silent bins stay finite.
+pub fn power_levels_db(spectrum_data: &[SpectrumData], full_scale_power: f32, levels_db: &mut [f32]) {
+    let length = spectrum_data.len().min(levels_db.len());
+    let spectrum_data = &spectrum_data[..length];
+    let levels_db = &mut levels_db[..length];
+    let scale = 1.0 / full_scale_power.max(f32::MIN_POSITIVE);
+
+    let mut level_chunks = levels_db.chunks_exact_mut(CHUNK_SIZE);
+    let mut data_chunks = spectrum_data.chunks_exact(CHUNK_SIZE);
+    for (levels, data) in (&mut level_chunks).zip(&mut data_chunks) {
+        for i in 0..CHUNK_SIZE {
+            levels[i] = 10.0 * (data[i].power * scale).max(1e-12).log10();
+        }
+    }
+
+    for (level, data) in level_chunks.into_remainder().iter_mut().zip(data_chunks.remainder()) {
+        *level = 10.0 * (data.power * scale).max(1e-12).log10();
+    }
+}
+
+// Computes the chroma vector for the current spectrum, re-estimating the tuning first if enabled
+fn get_chroma(analyzer: &mut Analyzer) {
+    let spectrum = &analyzer.spectrum_data[..analyzer.nyquist_limit];
+
+    if analyzer.chroma_config.estimate_tuning {
+        analyzer.tuning_offset = estimate_tuning(spectrum,
+                                                 analyzer.chroma_config.min_frequency,
+                                                 analyzer.chroma_config.max_frequency);
+    }
+
+    // A full-scale sine produces a Hann-windowed peak of window_size / 4
+    let full_scale = analyzer.window_size as f32 / 4.0;
+    compute_chroma(spectrum, &analyzer.chroma_config, analyzer.tuning_offset, full_scale, &mut analyzer.chroma);
+}
+
+// Feeds the current chroma vector to the chord tracker and stores any chord segment that ended
+fn track_chords(analyzer: &mut Analyzer) {
+    let mut semitones = [0.0; 12];
+    fold_to_semitones(&analyzer.chroma, &mut semitones);
+
+    let frame_time = analyzer.samples_processed as f32 / analyzer.sample_rate;
+
+    if let Some(segment) = analyzer.chord_tracker.push_frame(&semitones, frame_time) {
+        if analyzer.chord_history.len() == MAX_CHORD_HISTORY {
+            analyzer.chord_history.remove(0);
+        }
+        analyzer.chord_history.push(segment);
+    }
+}
+
+// Maps the candidates of the most recent estimate to MIDI pitches and advances the note tracker
+// by one estimator hop
+fn identify_notes(analyzer: &mut Analyzer, frame_duration: f32) {
+    // Start from silence; each candidate raises the level of its pitch
+    analyzer.pitch_levels = [SILENCE_DB; NUM_PITCHES];
+
+    // The frame ends at the current sample position
+    let frame_start = (analyzer.samples_processed as f32 / analyzer.sample_rate - frame_duration).max(0.0);
+
+    for candidate in &analyzer.candidates {
+        if candidate.frequency < analyzer.min_frequency || candidate.frequency > analyzer.max_frequency {
+            continue;
+        }
+
+        let midi_note = match frequency_to_note(candidate.frequency) {
+            Some(midi_note) => midi_note,
+            None => continue,
+        };
+
+        // Several candidates can round to the same pitch; the loudest one describes the note
+        if candidate.level_db <= analyzer.pitch_levels[midi_note] {
+            continue;
+        }
+
+        analyzer.pitch_levels[midi_note] = candidate.level_db;
+        analyzer.pitch_notes[midi_note] = DetectedNote::new(midi_note,
+                                                            candidate.frequency,
+                                                            candidate.level_db,
+                                                            candidate.salience,
+                                                            frame_start);
+    }
+
+    analyzer.note_tracker.update(&analyzer.pitch_levels, frame_start, frame_duration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chord_recognition::{Chord, ChordQuality};
+    use crate::logging::LogCollector;
+    use triple_buffer::TripleBuffer;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    // Everything around an analyzer: the queue that feeds it, the editor's side of its results
+    // and the log it writes to
+    struct Harness {
+        analyzer: Analyzer,
+        producer: rtrb::Producer<f32>,
+        output: triple_buffer::Output<AnalysisFrame>,
+        log: LogCollector,
+    }
+
+    fn harness() -> Harness {
+        let (_, unused_consumer) = rtrb::RingBuffer::new(1);
+        let (input, output) = TripleBuffer::new(&AnalysisFrame::default()).split();
+        let mut log = LogCollector::default();
+        let analyzer = Analyzer::new(unused_consumer, input, log.logger());
+        let (producer, _) = rtrb::RingBuffer::new(1);
+        Harness { analyzer, producer, output, log }
+    }
+
+    // A harness whose analyzer is initialized and fed through a queue of `capacity` samples
+    fn initialized_harness(capacity: usize) -> Harness {
+        let mut harness = harness();
+        let (producer, consumer) = rtrb::RingBuffer::new(capacity);
+        harness.analyzer.initialize(SAMPLE_RATE, consumer).unwrap();
+        harness.producer = producer;
+        harness
+    }
+
+    fn block_info(suppress_percussion: bool) -> BlockInfo {
+        BlockInfo {
+            position_beats: None,
+            tempo: None,
+            beats_per_bar: 4,
+            peak_level_db: FLOOR_DB,
+            rms_level_db: FLOOR_DB,
+            buffer_size: 512,
+            mode: DetectionMode::Polyphonic,
+            min_frequency: 20.0,
+            max_frequency: 20000.0,
+            note_threshold_db: NoteTrackerConfig::default().on_threshold_db,
+            suppress_percussion,
+            onset_method: OnsetMethod::SpectralFlux,
+        }
+    }
+
+    fn assert_frame_is_finite(frame: &AnalysisFrame) {
+        assert!(frame.spectrum.iter().all(|level| level.is_finite()));
+        assert!(frame.spectrogram_column.iter().all(|level| level.is_finite()));
+        assert!(frame.notes().iter().all(|note| note.frequency.is_finite()));
+    }
+
+    #[test]
+    fn bins_are_equally_wide_at_every_sample_rate() {
+        for sample_rate in [22050.0, 44100.0, 48000.0, 88200.0, 96000.0, 192000.0] {
+            let mut harness = harness();
+            let (_, consumer) = rtrb::RingBuffer::new(1);
+            harness.analyzer.initialize(sample_rate, consumer).unwrap();
+
+            let analyzer = &harness.analyzer;
+            assert!(analyzer.fft_size >= analyzer.window_size, "{} Hz", sample_rate);
+            assert_eq!(analyzer.frequency_resolution, FFT_BIN_WIDTH_HZ, "{} Hz", sample_rate);
+            assert_eq!(analyzer.spectrum_data[1].frequency, FFT_BIN_WIDTH_HZ, "{} Hz", sample_rate);
+        }
+
+        // Frames longer than the target size are transformed as they are, padded to an even size
+        assert_eq!(analysis_fft_size(1000.0, 2001), 2002);
+    }
+
+    #[test]
+    fn buffer_sizes_are_logged_in_full() {
+        let mut harness = initialized_harness(1);
+        harness.log.collect();
+
+        let ending = format!("history of {} samples", harness.analyzer.estimator_history.len());
+        assert!(harness.log.history().any(|record| record.message().starts_with("Analysis prepared")
+            && record.message().ends_with(&ending)));
+    }
+
+    #[test]
+    fn running_before_initialize_is_an_error() {
+        let mut harness = harness();
+        let result = harness.analyzer.run(AnalysisTask::Analyze(block_info(false)));
+        assert_eq!(result, Err(AnalysisError::NotInitialized));
+
+        // Tasks that only drop state need no sample rate
+        assert_eq!(harness.analyzer.run(AnalysisTask::Reset), Ok(()));
+        assert_eq!(harness.analyzer.run(AnalysisTask::ClearHistory), Ok(()));
+    }
+
+    #[test]
+    fn invalid_sample_rates_leave_the_analyzer_uninitialized() {
+        let mut harness = harness();
+
+        for sample_rate in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let (_, consumer) = rtrb::RingBuffer::new(1);
+            let result = harness.analyzer.initialize(sample_rate, consumer);
+            assert!(matches!(result, Err(AnalysisError::InvalidSampleRate(_))), "{}", sample_rate);
+        }
+
+        let result = harness.analyzer.run(AnalysisTask::Analyze(block_info(false)));
+        assert_eq!(result, Err(AnalysisError::NotInitialized));
+    }
+
+    #[test]
+    fn invalid_samples_in_the_queue_are_analysed_as_silence() {
+        // Enough samples for a full FFT frame, so that every stage sees the invalid samples
+        let num_samples = 70_000;
+        let invalid = [f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+
+        for suppress_percussion in [false, true] {
+            let mut harness = initialized_harness(num_samples);
+            let mut num_invalid = 0;
+
+            for i in 0..num_samples {
+                let sample = match i % 100 {
+                    0 => {
+                        num_invalid += 1;
+                        invalid[i / 100 % invalid.len()]
+                    }
+                    50 => f32::MIN_POSITIVE / 2.0,
+                    _ => 0.25 * (2.0 * PI * 220.0 * i as f32 / SAMPLE_RATE).sin(),
+                };
+                harness.producer.push(sample).unwrap();
+            }
+
+            let result = harness.analyzer.run(AnalysisTask::Analyze(block_info(suppress_percussion)));
+            assert_eq!(result, Ok(()));
+            assert_frame_is_finite(harness.output.read());
+
+            let warning = format!("{} NaN or infinite samples were analysed as silence", num_invalid);
+            harness.log.collect();
+            assert!(harness.log.history().any(|record| record.message() == warning));
+        }
+    }
+
+    #[test]
+    fn blocks_of_only_invalid_samples_are_silence() {
+        let mut harness = initialized_harness(70_000);
+
+        for i in 0..70_000 {
+            harness.producer.push(if i % 2 == 0 { f32::NAN } else { f32::INFINITY }).unwrap();
+        }
+
+        assert_eq!(harness.analyzer.run(AnalysisTask::Analyze(block_info(false))), Ok(()));
+        let frame = harness.output.read();
+        assert_frame_is_finite(frame);
+        assert!(frame.notes().is_empty());
+    }
+
+    // Pushes `seconds` of a chord made of sines at `frequencies`, continuing from sample `start`
+    fn push_chord(harness: &mut Harness, frequencies: &[f32], start: usize, seconds: f32) -> usize {
+        let num_samples = (seconds * SAMPLE_RATE) as usize;
+        for i in start..start + num_samples {
+            let time = i as f32 / SAMPLE_RATE;
+            let sample: f32 = frequencies.iter().map(|frequency| 0.1 * (2.0 * PI * frequency * time).sin()).sum();
+            harness.producer.push(sample).unwrap();
+        }
+        start + num_samples
+    }
+
+    #[test]
+    fn triads_are_recognized_as_their_chords() {
+        let c_major = Chord { root: 0, quality: ChordQuality::Major };
+        let a_minor = Chord { root: 9, quality: ChordQuality::Minor };
+
+        for suppress_percussion in [false, true] {
+            let mut harness = initialized_harness((12.0 * SAMPLE_RATE) as usize);
+
+            // C4, E4 and G4, then A3, C4 and E4
+            let position = push_chord(&mut harness, &[261.63, 329.63, 392.0], 0, 6.0);
+            push_chord(&mut harness, &[220.0, 261.63, 329.63], position, 6.0);
+            assert_eq!(harness.analyzer.run(AnalysisTask::Analyze(block_info(suppress_percussion))), Ok(()));
+
+            let chords: Vec<_> = harness.analyzer.chord_segments().iter().map(|segment| segment.chord).collect();
+            assert_eq!(chords, [Some(c_major)], "percussion suppression {}", suppress_percussion);
+            assert_eq!(harness.output.read().chord, Some(Some(a_minor)), "percussion suppression {}", suppress_percussion);
+        }
+    }
+
+    #[test]
+    fn polyphonic_notes_come_from_the_fft_frames_and_release_in_silence() {
+        let mut harness = initialized_harness((8.0 * SAMPLE_RATE) as usize);
+
+        // A4 and E5 for three FFT frames
+        let position = push_chord(&mut harness, &[440.0, 659.26], 0, 3.0 * WINDOW_LENGTH_MS / 1000.0);
+        assert_eq!(harness.analyzer.run(AnalysisTask::Analyze(block_info(false))), Ok(()));
+
+        let notes: Vec<u8> = harness.output.read().notes().iter().map(|note| note.midi).collect();
+        assert_eq!(notes, [69, 76]);
+        assert!(harness.output.read().frame_timings.estimator_ms > 0.0);
+
+        // Silence still produces frames, so the notes are released
+        push_chord(&mut harness, &[], position, 3.0 * WINDOW_LENGTH_MS / 1000.0);
+        assert_eq!(harness.analyzer.run(AnalysisTask::Analyze(block_info(false))), Ok(()));
+        assert!(harness.output.read().notes().is_empty());
+    }
+}
@@ -0,0 +1,447 @@
+use crate::chord_recognition::{Chord, ChordSegment};
+use crate::onset_detection::{onset_envelope, OnsetConfig};
+
+// Tempo range considered by the tempo estimator, in beats per minute
+const MIN_TEMPO: f32 = 60.0;
+const MAX_TEMPO: f32 = 200.0;
+
+// Centre and width (in octaves) of the tempo prior. Tempi far from the centre need a much
+// stronger autocorrelation peak to win, which avoids picking half or double time.
+const PRIOR_TEMPO: f32 = 120.0;
+const PRIOR_WIDTH: f32 = 1.0;
+
+// Half-width of the triangular window the envelope is smoothed with before estimating the tempo,
+// in envelope frames
+const TEMPO_SMOOTHING: usize = 2;
+
+// Penalty for beat intervals that deviate from the estimated tempo. Higher values give a more
+// rigid beat grid.
+const TIGHTNESS: f32 = 100.0;
+
+// Most beats per bar the chord grid can hold
+const MAX_BEATS_PER_BAR: usize = 16;
+
+// Most bars the chord grid can hold
+pub const MAX_GRID_BARS: usize = 8;
+
+// Result of offline beat tracking
+#[derive(Debug, Clone)]
+pub struct BeatTrack {
+    // Estimated tempo in beats per minute
+    pub tempo: f32,
+
+    // Beat times in seconds
+    pub beats: Vec<f32>,
+}
+
+// Estimates the tempo of an onset strength envelope by autocorrelation, weighted by a log-normal
+// prior around 120 BPM. The envelope is smoothed first: sharp attacks only last a frame or two,
+// so without it a beat period that falls between two whole frames correlates poorly and a
+// multiple of it that happens to be close to a whole number of frames wins instead.
+pub fn estimate_tempo(envelope: &[f32], frame_rate: f32) -> f32 {
+    let smoothed: Vec<f32> = (0..envelope.len())
+        .map(|i| {
+            let start = i.saturating_sub(TEMPO_SMOOTHING);
+            let end = (i + TEMPO_SMOOTHING + 1).min(envelope.len());
+            envelope[start..end].iter()
+                .enumerate()
+                .map(|(j, value)| value * (TEMPO_SMOOTHING + 1 - (start + j).abs_diff(i)) as f32)
+                .sum::<f32>()
+        })
+        .collect();
+
+    let mean = smoothed.iter().sum::<f32>() / smoothed.len().max(1) as f32;
+    let centered: Vec<f32> = smoothed.iter().map(|value| value - mean).collect();
+
+    let min_lag = (60.0 * frame_rate / MAX_TEMPO).floor().max(1.0) as usize;
+    let max_lag = ((60.0 * frame_rate / MIN_TEMPO).ceil() as usize).min(centered.len().saturating_sub(1));
+
+    let mut best_tempo = PRIOR_TEMPO;
+    let mut best_score = f32::MIN;
+
+    for lag in min_lag..=max_lag {
+        let correlation: f32 = centered.iter()
+            .zip(centered[lag..].iter())
+            .map(|(a, b)| a * b)
+            .sum();
+
+        let tempo = 60.0 * frame_rate / lag as f32;
+        let octaves = (tempo / PRIOR_TEMPO).log2() / PRIOR_WIDTH;
+        let score = correlation * (-0.5 * octaves * octaves).exp();
+
+        if score > best_score {
+            best_score = score;
+            best_tempo = tempo;
+        }
+    }
+
+    best_tempo
+}
+
+// Dynamic programming beat tracker (Ellis 2007). Each frame's score is its onset strength plus the
+// best score of a previous beat roughly one beat period earlier, penalized by how far the interval
+// is from the period. Returns beat positions as envelope frame indices.
+pub fn track_beats(envelope: &[f32], frame_rate: f32, tempo: f32) -> Vec<usize> {
+    if envelope.is_empty() {
+        return Vec::new();
+    }
+
+    let period = 60.0 * frame_rate / tempo;
+
+    // Normalize the envelope so that TIGHTNESS has a consistent meaning
+    let deviation = (envelope.iter().map(|value| value * value).sum::<f32>() / envelope.len() as f32).sqrt();
+    let normalized: Vec<f32> = envelope.iter().map(|value| value / deviation.max(1e-9)).collect();
+
+    let mut scores = vec![0.0; normalized.len()];
+    let mut backlinks: Vec<Option<usize>> = vec![None; normalized.len()];
+
+    let min_interval = (period / 2.0).round().max(1.0) as usize;
+    let max_interval = (period * 2.0).round() as usize;
+
+    for t in 0..normalized.len() {
+        let mut best_previous = None;
+        let mut best_score = 0.0;
+
+        for interval in min_interval..=max_interval.min(t) {
+            let previous = t - interval;
+            let penalty = TIGHTNESS * (interval as f32 / period).ln().powi(2);
+            let score = scores[previous] - penalty;
+
+            if best_previous.is_none() || score > best_score {
+                best_previous = Some(previous);
+                best_score = score;
+            }
+        }
+
+        scores[t] = normalized[t] + best_previous.map_or(0.0, |_| best_score);
+        backlinks[t] = best_previous;
+    }
+
+    // The last beat is the best scoring frame within the final beat period
+    let search_start = normalized.len().saturating_sub(period.round() as usize + 1);
+    let mut beat = (search_start..normalized.len())
+        .max_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+        .unwrap_or(normalized.len() - 1);
+
+    let mut beats = vec![beat];
+    while let Some(previous) = backlinks[beat] {
+        beats.push(previous);
+        beat = previous;
+    }
+    beats.reverse();
+
+    beats
+}
+
+// Tempo estimation and beat tracking over a whole signal
+pub fn beat_track(samples: &[f32], sample_rate: f32) -> BeatTrack {
+    let (envelope, frame_rate) = onset_envelope(samples, sample_rate, OnsetConfig::default());
+    let tempo = estimate_tempo(&envelope, frame_rate);
+
+    let beats = track_beats(&envelope, frame_rate, tempo)
+        .into_iter()
+        .map(|frame| frame as f32 / frame_rate)
+        .collect();
+
+    BeatTrack { tempo, beats }
+}
+
+// Assigns a chord to every beat interval, picking the chord with the most overlap. The last beat
+// is assumed to last as long as the one before it.
+pub fn chords_per_beat(segments: &[ChordSegment], beats: &[f32]) -> Vec<Option<Chord>> {
+    let mut beat_chords = Vec::with_capacity(beats.len());
+
+    for (i, &start) in beats.iter().enumerate() {
+        let end = match beats.get(i + 1) {
+            Some(&next) => next,
+            None if i > 0 => start + (start - beats[i - 1]),
+            None => start + 0.5,
+        };
+
+        let mut best_chord = None;
+        let mut best_overlap = 0.0;
+
+        for segment in segments {
+            let overlap = segment.end.min(end) - segment.start.max(start);
+            if overlap > best_overlap {
+                best_overlap = overlap;
+                best_chord = segment.chord;
+            }
+        }
+
+        beat_chords.push(best_chord);
+    }
+
+    beat_chords
+}
+
+// Formats one bar with chords named by chord_name. A chord that fills the whole bar is written
+// once; otherwise each change is written in order, e.g. "G Em".
+fn format_bar(beats: &[Option<Chord>], chord_name: &impl Fn(&Chord) -> String) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let mut previous = None;
+
+    for (i, &chord) in beats.iter().enumerate() {
+        if i > 0 && chord == previous {
+            continue;
+        }
+        previous = chord;
+
+        names.push(match chord {
+            Some(chord) => chord_name(&chord),
+            None => "N.C.".to_string(),
+        });
+    }
+
+    names.join(" ")
+}
+
+// Writes a progression as bars, e.g. "| G | Em | C | D |"
+pub fn format_progression(beat_chords: &[Option<Chord>], beats_per_bar: usize) -> String {
+    if beat_chords.is_empty() {
+        return String::new();
+    }
+
+    let bars: Vec<String> = beat_chords
+        .chunks(beats_per_bar.max(1))
+        .map(|bar| format_bar(bar, &Chord::to_string))
+        .collect();
+
+    format!("| {} |", bars.join(" | "))
+}
+
+// Real-time chord grid driven by the host transport. Each beat is labelled with the chord that
+// was current when the beat finished; the most recent bars are kept for display. Storage is
+// fixed-size so that the grid can be copied into an analysis frame without allocating.
+#[derive(Debug, Clone, Copy)]
+pub struct ChordGrid {
+    // Beat chords of the kept bars, oldest first. Each bar holds MAX_BEATS_PER_BAR slots, of
+    // which only the first beats_per_bar are used. Beats that have not been heard are None.
+    bars: [[Option<Option<Chord>>; MAX_BEATS_PER_BAR]; MAX_GRID_BARS],
+    num_bars: usize,
+    max_bars: usize,
+    beats_per_bar: usize,
+
+    // Index of the last beat that has been written, counted from the transport origin
+    last_beat: Option<i64>,
+}
+
+impl ChordGrid {
+    pub fn new(max_bars: usize) -> Self {
+        Self {
+            bars: [[None; MAX_BEATS_PER_BAR]; MAX_GRID_BARS],
+            num_bars: 0,
+            max_bars: max_bars.clamp(1, MAX_GRID_BARS),
+            beats_per_bar: 4,
+            last_beat: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.num_bars = 0;
+        self.last_beat = None;
+    }
+
+    // Updates the grid for a transport position in beats. Every beat completed since the last
+    // call is assigned the current chord. Jumping backwards or changing the meter starts over.
+    pub fn record(&mut self, position_beats: f64, beats_per_bar: usize, chord: Option<Chord>) {
+        let beats_per_bar = beats_per_bar.clamp(1, MAX_BEATS_PER_BAR);
+        let completed_beat = position_beats.floor() as i64 - 1;
+
+        if beats_per_bar != self.beats_per_bar || self.last_beat.is_some_and(|last| completed_beat < last) {
+            self.reset();
+            self.beats_per_bar = beats_per_bar;
+        }
+
+        // Start at the current beat when starting out, so that beats that were never heard stay
+        // empty
+        let first_beat = match self.last_beat {
+            Some(last) => last + 1,
+            None => completed_beat.max(0),
+        };
+
+        for beat in first_beat..=completed_beat {
+            let beat_in_bar = beat.rem_euclid(beats_per_bar as i64) as usize;
+
+            if beat_in_bar == 0 || self.num_bars == 0 {
+                if self.num_bars == self.max_bars {
+                    self.bars.copy_within(1..self.num_bars, 0);
+                    self.num_bars -= 1;
+                }
+                self.bars[self.num_bars] = [None; MAX_BEATS_PER_BAR];
+                self.num_bars += 1;
+            }
+
+            self.bars[self.num_bars - 1][beat_in_bar] = Some(chord);
+            self.last_beat = Some(beat);
+        }
+    }
+
+    // Records a chord that was decided `latency` seconds of audio ago. The transport position is
+    // moved back by that time at `tempo` beats per minute, so the chord lands in the beats that
+    // were playing when its audio was, not in the ones playing when the decision came in.
+    pub fn record_delayed(&mut self, position_beats: f64, tempo: f64, latency: f64, beats_per_bar: usize, chord: Option<Chord>) {
+        self.record(position_beats - latency.max(0.0) * tempo / 60.0, beats_per_bar, chord);
+    }
+
+    // The kept bars written as "| G | Em | C | D |", with chords named by chord_name
+    pub fn progression(&self, chord_name: impl Fn(&Chord) -> String) -> String {
+        if self.num_bars == 0 {
+            return String::new();
+        }
+
+        let mut beats = Vec::with_capacity(self.beats_per_bar);
+        let bars: Vec<String> = self.bars[..self.num_bars]
+            .iter()
+            .map(|bar| {
+                beats.clear();
+                beats.extend(bar[..self.beats_per_bar].iter().flatten());
+                format_bar(&beats, &chord_name)
+            })
+            .collect();
+
+        format!("| {} |", bars.join(" | "))
+    }
+
+    // The kept bars as a ChordPro grid section with one cell per beat. A cell holds the chord
+    // where it changes and "." where it continues, e.g. "| G . . . | Em . C . |".
+    pub fn chordpro_grid(&self, chord_name: impl Fn(&Chord) -> String) -> String {
+        if self.num_bars == 0 {
+            return String::new();
+        }
+
+        let mut previous = None;
+        let bars: Vec<String> = self.bars[..self.num_bars]
+            .iter()
+            .map(|bar| {
+                bar[..self.beats_per_bar]
+                    .iter()
+                    .map(|&beat| {
+                        let cell = match beat {
+                            Some(chord) if beat != previous => match chord {
+                                Some(chord) => chord_name(&chord),
+                                None => "N.C.".to_string(),
+                            },
+                            _ => ".".to_string(),
+                        };
+                        if beat.is_some() {
+                            previous = beat;
+                        }
+                        cell
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            })
+            .collect();
+
+        format!("{{start_of_grid}}\n| {} |\n{{end_of_grid}}", bars.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chord_recognition::ChordQuality;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    // Clicks of a few milliseconds of decaying noise at a steady tempo
+    fn click_track(tempo: f32, seconds: f32) -> Vec<f32> {
+        let mut samples = vec![0.0; (seconds * SAMPLE_RATE) as usize];
+        let period = (60.0 / tempo * SAMPLE_RATE) as usize;
+        let click_length = (0.005 * SAMPLE_RATE) as usize;
+        let mut noise: u32 = 1;
+
+        for start in (0..samples.len()).step_by(period) {
+            for (i, sample) in samples[start..].iter_mut().take(click_length).enumerate() {
+                noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let white = (noise >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0;
+                *sample = white * (-(i as f32) / (click_length as f32 / 4.0)).exp();
+            }
+        }
+
+        samples
+    }
+
+    #[test]
+    fn click_track_tempo_and_beats() {
+        for tempo in [90.0, 120.0, 150.0] {
+            let track = beat_track(&click_track(tempo, 12.0), SAMPLE_RATE);
+            assert!((track.tempo - tempo).abs() < tempo * 0.03, "{} BPM estimated as {}", tempo, track.tempo);
+
+            // Every beat lands on a click, up to the onset detector's frame length
+            let period = 60.0 / tempo;
+            assert!(track.beats.len() as f32 >= 12.0 / period - 2.0, "{} beats at {} BPM", track.beats.len(), tempo);
+            for &beat in &track.beats {
+                let offset = beat - (beat / period).round() * period;
+                assert!(offset.abs() < 0.05, "beat at {} s is {} s off the clicks at {} BPM", beat, offset, tempo);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_has_no_beats_to_follow() {
+        let track = beat_track(&vec![0.0; (4.0 * SAMPLE_RATE) as usize], SAMPLE_RATE);
+        assert!(track.tempo.is_finite());
+        assert!(track.beats.iter().all(|beat| beat.is_finite()));
+    }
+
+    #[test]
+    fn chords_are_assigned_to_beats_and_bars() {
+        let chord = |root| Some(Chord { root, quality: ChordQuality::Major });
+        let segments = [
+            ChordSegment { chord: chord(7), start: 0.0, end: 2.1 },
+            ChordSegment { chord: chord(0), start: 2.1, end: 3.0 },
+            ChordSegment { chord: chord(2), start: 3.0, end: 4.0 },
+        ];
+        let beats: Vec<f32> = (0..8).map(|beat| beat as f32 * 0.5).collect();
+
+        let beat_chords = chords_per_beat(&segments, &beats);
+        assert_eq!(beat_chords, [chord(7), chord(7), chord(7), chord(7), chord(0), chord(0), chord(2), chord(2)]);
+        assert_eq!(format_progression(&beat_chords, 4), "| G | C D |");
+    }
+
+    #[test]
+    fn grid_follows_the_transport() {
+        let chord = |root| Some(Chord { root, quality: ChordQuality::Minor });
+        let mut grid = ChordGrid::new(2);
+
+        grid.record(4.0, 4, chord(9));
+        grid.record(8.0, 4, chord(4));
+        assert_eq!(grid.progression(|chord| chord.to_string()), "| Am | Em |");
+
+        // Jumping back starts over
+        grid.record(1.0, 4, chord(2));
+        assert_eq!(grid.progression(|chord| chord.to_string()), "| Dm |");
+    }
+
+    #[test]
+    fn delayed_chords_land_in_the_bar_they_were_played_in() {
+        // At 120 BPM a 4/4 bar lasts 2 s. C plays for two bars, then G. Frames end every second
+        // and each decision arrives two frames later, polled every quarter of a second.
+        let (tempo, lag) = (120.0, 2.0);
+        let chord_of_frame = |end: f64| {
+            let root = if end <= 4.0 { 0 } else { 7 };
+            Some(Chord { root, quality: ChordQuality::Major })
+        };
+
+        let mut grid = ChordGrid::new(8);
+        let mut late_grid = ChordGrid::new(8);
+        for step in 0..=48 {
+            let now = step as f64 * 0.25;
+            let frame_end = (now - lag).floor();
+            if frame_end < 0.0 {
+                continue;
+            }
+
+            let position_beats = now * tempo / 60.0;
+            grid.record_delayed(position_beats, tempo, now - frame_end, 4, chord_of_frame(frame_end));
+            late_grid.record(position_beats, 4, chord_of_frame(frame_end));
+        }
+
+        assert_eq!(grid.progression(|chord| chord.to_string()), "| C | C | G | G | G |");
+
+        // Without the compensation the change to G shows up a bar late
+        assert!(late_grid.progression(|chord| chord.to_string()).starts_with("| C | C | C |"));
+    }
+}
@@ -0,0 +1,564 @@
+use std::fmt;
+use crate::sanitize::flush_denormals;
+
+// Pitch class names used when printing chord roots, starting at C
+const PITCH_CLASS_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Chord qualities that have a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Dominant7,
+    Minor7,
+    Diminished,
+}
+
+impl ChordQuality {
+    const ALL: [ChordQuality; 5] = [
+        ChordQuality::Major,
+        ChordQuality::Minor,
+        ChordQuality::Dominant7,
+        ChordQuality::Minor7,
+        ChordQuality::Diminished,
+    ];
+
+    // Semitone intervals above the root
+    fn intervals(&self) -> &'static [usize] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::Diminished => &[0, 3, 6],
+        }
+    }
+
+    // Text written after the root in a chord name
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Minor7 => "m7",
+            ChordQuality::Diminished => "dim",
+        }
+    }
+}
+
+// A chord label. The root is a pitch class where 0 is C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub root: usize,
+    pub quality: ChordQuality,
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PITCH_CLASS_NAMES[self.root % 12], self.quality.suffix())
+    }
+}
+
+// A stretch of time over which one chord (or no chord) was recognized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChordSegment {
+    // None means no chord, e.g. silence or noise
+    pub chord: Option<Chord>,
+
+    // Start and end time in seconds
+    pub start: f32,
+    pub end: f32,
+}
+
+// How chord templates are built
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    // 1.0 on every chord tone, 0.0 elsewhere
+    Binary,
+
+    // Every chord tone also contributes its first few harmonics with decaying weight, which
+    // better matches the chroma of real instruments
+    Harmonic,
+}
+
+// Settings for the chord recognizer and its hidden Markov model
+#[derive(Debug, Clone)]
+pub struct ChordRecognizerConfig {
+    pub template_kind: TemplateKind,
+
+    // Probability of staying on the same chord from one frame to the next. Higher values give
+    // more stable but slower-reacting output.
+    pub self_transition: f32,
+
+    // Scales template similarity before it is turned into an emission probability. Higher values
+    // trust each individual frame more.
+    pub emission_sharpness: f32,
+
+    // Number of frames the real-time tracker waits before committing to a decision
+    pub lag_frames: usize,
+}
+
+impl Default for ChordRecognizerConfig {
+    fn default() -> Self {
+        Self {
+            template_kind: TemplateKind::Harmonic,
+            self_transition: 0.8,
+            emission_sharpness: 20.0,
+            lag_frames: 2,
+        }
+    }
+}
+
+// Number of harmonics added per chord tone for harmonic templates
+const TEMPLATE_HARMONICS: usize = 4;
+
+// Weight decay for each additional harmonic in harmonic templates
+const HARMONIC_DECAY: f32 = 0.6;
+
+// Scores chroma frames against chord templates. State 0 is "no chord"; every other state is a
+// root/quality combination.
+pub struct ChordRecognizer {
+    config: ChordRecognizerConfig,
+    states: Vec<Option<Chord>>,
+    templates: Vec<[f32; 12]>,
+}
+
+impl ChordRecognizer {
+    pub fn new(config: ChordRecognizerConfig) -> Self {
+        let mut states = vec![None];
+        let mut templates = vec![[1.0; 12]];
+
+        for quality in ChordQuality::ALL {
+            for root in 0..12 {
+                let chord = Chord { root, quality };
+                states.push(Some(chord));
+                templates.push(build_template(chord, config.template_kind));
+            }
+        }
+
+        for template in templates.iter_mut() {
+            normalize(template);
+        }
+
+        Self { config, states, templates }
+    }
+
+    pub fn config(&self) -> &ChordRecognizerConfig {
+        &self.config
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn state_chord(&self, state: usize) -> Option<Chord> {
+        self.states[state]
+    }
+
+    // Cosine similarity between the chroma frame and every template, written to `scores`
+    pub fn score_frame(&self, chroma: &[f32; 12], scores: &mut [f32]) {
+        let mut frame = *chroma;
+        normalize(&mut frame);
+
+        for (score, template) in scores.iter_mut().zip(self.templates.iter()) {
+            *score = frame.iter().zip(template.iter()).map(|(a, b)| a * b).sum();
+        }
+    }
+
+    // Best single-frame match, without any smoothing
+    pub fn classify_frame(&self, chroma: &[f32; 12]) -> Option<Chord> {
+        let mut scores = vec![0.0; self.num_states()];
+        self.score_frame(chroma, &mut scores);
+        self.states[argmax(&scores)]
+    }
+
+    fn log_transitions(&self) -> (f32, f32) {
+        let stay = self.config.self_transition.clamp(1e-6, 1.0 - 1e-6);
+        let switch = (1.0 - stay) / (self.num_states() - 1) as f32;
+        (stay.ln(), switch.ln())
+    }
+
+    // Offline decoding of a whole chroma sequence with the Viterbi algorithm. Frames are assumed
+    // to be `hop_seconds` apart. Consecutive frames with the same chord are merged into segments.
+    pub fn viterbi(&self, frames: &[[f32; 12]], hop_seconds: f32) -> Vec<ChordSegment> {
+        if frames.is_empty() {
+            return Vec::new();
+        }
+
+        let num_states = self.num_states();
+        let (log_stay, log_switch) = self.log_transitions();
+
+        let mut scores = vec![0.0; num_states];
+        let mut delta = vec![0.0; num_states];
+        let mut next_delta = vec![0.0; num_states];
+        let mut backpointers = vec![vec![0usize; num_states]; frames.len()];
+
+        self.score_frame(&frames[0], &mut scores);
+        for (d, score) in delta.iter_mut().zip(scores.iter()) {
+            *d = self.config.emission_sharpness * score;
+        }
+
+        for (t, frame) in frames.iter().enumerate().skip(1) {
+            self.score_frame(frame, &mut scores);
+
+            // With a uniform switching probability, the best predecessor of a state is either the
+            // state itself or the best of all other states
+            let (best, second_best) = best_two(&delta);
+
+            for j in 0..num_states {
+                let other = if j == best { second_best } else { best };
+                let stay_score = delta[j] + log_stay;
+                let switch_score = delta[other] + log_switch;
+
+                let (previous, score) = if stay_score >= switch_score {
+                    (j, stay_score)
+                } else {
+                    (other, switch_score)
+                };
+
+                backpointers[t][j] = previous;
+                next_delta[j] = score + self.config.emission_sharpness * scores[j];
+            }
+
+            std::mem::swap(&mut delta, &mut next_delta);
+        }
+
+        // Trace back the most likely state sequence
+        let mut path = vec![0usize; frames.len()];
+        path[frames.len() - 1] = argmax(&delta);
+        for t in (1..frames.len()).rev() {
+            path[t - 1] = backpointers[t][path[t]];
+        }
+
+        let mut segments: Vec<ChordSegment> = Vec::new();
+        for (t, &state) in path.iter().enumerate() {
+            let chord = self.states[state];
+            let start = t as f32 * hop_seconds;
+            let end = start + hop_seconds;
+
+            match segments.last_mut() {
+                Some(segment) if segment.chord == chord => segment.end = end,
+                _ => segments.push(ChordSegment { chord, start, end }),
+            }
+        }
+
+        segments
+    }
+}
+
+// Real-time chord tracking using HMM forward filtering with fixed-lag smoothing. Each new frame
+// updates the filtered state probabilities; the decision for the frame `lag_frames` back is made
+// using the frames that followed it, which removes most single-frame flicker at a bounded delay.
+pub struct OnlineChordTracker {
+    recognizer: ChordRecognizer,
+
+    // Filtered (forward) probabilities for the last lag_frames + 1 frames, oldest first
+    forward_history: Vec<Vec<f32>>,
+
+    // Emission probabilities for the same frames
+    emission_history: Vec<Vec<f32>>,
+
+    // Start times of the same frames
+    time_history: Vec<f32>,
+
+    // Number of valid entries in the history buffers
+    history_len: usize,
+
+    // Scratch buffers
+    scores: Vec<f32>,
+    backward: Vec<f32>,
+    next_backward: Vec<f32>,
+
+    // Segment currently being extended
+    current_segment: Option<ChordSegment>,
+}
+
+impl OnlineChordTracker {
+    pub fn new(config: ChordRecognizerConfig) -> Self {
+        let recognizer = ChordRecognizer::new(config);
+        let num_states = recognizer.num_states();
+        let history_size = recognizer.config().lag_frames + 1;
+
+        Self {
+            forward_history: vec![vec![0.0; num_states]; history_size],
+            emission_history: vec![vec![0.0; num_states]; history_size],
+            time_history: vec![0.0; history_size],
+            history_len: 0,
+            scores: vec![0.0; num_states],
+            backward: vec![0.0; num_states],
+            next_backward: vec![0.0; num_states],
+            current_segment: None,
+            recognizer,
+        }
+    }
+
+    // Forgets all history, e.g. after a transport restart
+    pub fn reset(&mut self) {
+        self.history_len = 0;
+        self.current_segment = None;
+    }
+
+    // Segment for the most recently decided frame, if any
+    pub fn current_segment(&self) -> Option<ChordSegment> {
+        self.current_segment
+    }
+
+    // Time passed with the most recently decided frame, if a decision has been made
+    pub fn decided_frame_time(&self) -> Option<f32> {
+        (self.history_len == self.time_history.len()).then_some(self.time_history[0])
+    }
+
+    // Adds a chroma frame starting at `time` seconds. Returns the previous segment when the
+    // smoothed decision switches to a different chord.
+    pub fn push_frame(&mut self, chroma: &[f32; 12], time: f32) -> Option<ChordSegment> {
+        let num_states = self.recognizer.num_states();
+        let history_size = self.forward_history.len();
+        let sharpness = self.recognizer.config().emission_sharpness;
+        let stay = self.recognizer.config().self_transition;
+        let switch = (1.0 - stay) / (num_states - 1) as f32;
+
+        // Drop the oldest frame once the history is full
+        if self.history_len == history_size {
+            self.forward_history.rotate_left(1);
+            self.emission_history.rotate_left(1);
+            self.time_history.rotate_left(1);
+            self.history_len -= 1;
+        }
+
+        // Emission probabilities, scaled relative to the best score to avoid underflow
+        self.recognizer.score_frame(chroma, &mut self.scores);
+        let best_score = self.scores.iter().cloned().fold(f32::MIN, f32::max);
+        let index = self.history_len;
+        for (emission, score) in self.emission_history[index].iter_mut().zip(self.scores.iter()) {
+            *emission = (sharpness * (score - best_score)).exp();
+        }
+        flush_denormals(&mut self.emission_history[index]);
+        self.time_history[index] = time;
+
+        // Forward step: predict with the transition model, then weight by the emission
+        if index == 0 {
+            let (forward, emission) = (&mut self.forward_history[0], &self.emission_history[0]);
+            forward.copy_from_slice(emission);
+        } else {
+            let (previous, current) = self.forward_history.split_at_mut(index);
+            let previous = &previous[index - 1];
+            let current = &mut current[0];
+            let total: f32 = previous.iter().sum();
+
+            for j in 0..num_states {
+                let predicted = stay * previous[j] + switch * (total - previous[j]);
+                current[j] = predicted * self.emission_history[index][j];
+            }
+        }
+        normalize_sum(&mut self.forward_history[index]);
+        self.history_len += 1;
+
+        // Wait until enough future frames are available for the oldest one
+        if self.history_len < history_size {
+            return None;
+        }
+
+        // Backward pass over the lag window, then combine with the oldest forward probabilities
+        self.backward.iter_mut().for_each(|value| *value = 1.0);
+        for t in (1..history_size).rev() {
+            let mut total = 0.0;
+            for j in 0..num_states {
+                total += self.emission_history[t][j] * self.backward[j];
+            }
+            for i in 0..num_states {
+                let same = self.emission_history[t][i] * self.backward[i];
+                self.next_backward[i] = stay * same + switch * (total - same);
+            }
+            std::mem::swap(&mut self.backward, &mut self.next_backward);
+            normalize_sum(&mut self.backward);
+        }
+
+        for (score, (forward, backward)) in self.scores.iter_mut()
+            .zip(self.forward_history[0].iter().zip(self.backward.iter())) {
+            *score = forward * backward;
+        }
+
+        let chord = self.recognizer.state_chord(argmax(&self.scores));
+        let frame_start = self.time_history[0];
+        let frame_end = self.time_history[1];
+
+        match self.current_segment.as_mut() {
+            Some(segment) if segment.chord == chord => {
+                segment.end = frame_end;
+                None
+            }
+            _ => self.current_segment.replace(ChordSegment { chord, start: frame_start, end: frame_end }),
+        }
+    }
+}
+
+fn build_template(chord: Chord, kind: TemplateKind) -> [f32; 12] {
+    let mut template = [0.0; 12];
+
+    for interval in chord.quality.intervals() {
+        let pitch_class = chord.root + interval;
+
+        match kind {
+            TemplateKind::Binary => template[pitch_class % 12] = 1.0,
+            TemplateKind::Harmonic => {
+                for harmonic in 1..=TEMPLATE_HARMONICS {
+                    let offset = (12.0 * (harmonic as f32).log2()).round() as usize;
+                    template[(pitch_class + offset) % 12] += HARMONIC_DECAY.powi(harmonic as i32 - 1);
+                }
+            }
+        }
+    }
+
+    template
+}
+
+// Scales a vector to unit length
+fn normalize(values: &mut [f32; 12]) {
+    let norm = values.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        values.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+// Scales the values to sum to one. Values that sum to zero or to a non-finite number carry no
+// information and would stay that way in every later step, so they start over as uniform.
+fn normalize_sum(values: &mut [f32]) {
+    let total: f32 = values.iter().sum();
+    if total.is_finite() && total > 0.0 {
+        values.iter_mut().for_each(|value| *value /= total);
+        flush_denormals(values);
+    } else {
+        let uniform = 1.0 / values.len().max(1) as f32;
+        values.iter_mut().for_each(|value| *value = uniform);
+    }
+}
+
+// Index of the largest value. Ties go to the lowest index, so an all-zero frame maps to no chord.
+fn argmax(values: &[f32]) -> usize {
+    let mut best = 0;
+    for (i, &value) in values.iter().enumerate() {
+        if value > values[best] {
+            best = i;
+        }
+    }
+
+    best
+}
+
+// Indices of the largest and second largest values
+fn best_two(values: &[f32]) -> (usize, usize) {
+    let mut best = 0;
+    let mut second_best = if values.len() > 1 { 1 } else { 0 };
+    if values.len() > 1 && values[1] > values[0] {
+        best = 1;
+        second_best = 0;
+    }
+
+    for (i, &value) in values.iter().enumerate().skip(2) {
+        if value > values[best] {
+            second_best = best;
+            best = i;
+        } else if value > values[second_best] {
+            second_best = i;
+        }
+    }
+
+    (best, second_best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOP_SECONDS: f32 = 0.5;
+
+    const C_MAJOR: Chord = Chord { root: 0, quality: ChordQuality::Major };
+    const F_MAJOR: Chord = Chord { root: 5, quality: ChordQuality::Major };
+    const A_MINOR: Chord = Chord { root: 9, quality: ChordQuality::Minor };
+
+    // Chroma of a chord's tones over a little noise, as compute_chroma produces for a triad
+    fn chord_chroma(chord: Chord) -> [f32; 12] {
+        let mut chroma = [0.05; 12];
+        for interval in chord.quality.intervals() {
+            chroma[(chord.root + interval) % 12] = 1.0;
+        }
+        chroma
+    }
+
+    // Seven frames of C major with one frame of A minor among them, then six of F major
+    fn progression() -> Vec<[f32; 12]> {
+        let mut frames = vec![chord_chroma(C_MAJOR); 7];
+        frames[3] = chord_chroma(A_MINOR);
+        frames.extend([chord_chroma(F_MAJOR); 6]);
+        frames
+    }
+
+    #[test]
+    fn frames_are_classified_as_their_triads() {
+        for template_kind in [TemplateKind::Binary, TemplateKind::Harmonic] {
+            let recognizer = ChordRecognizer::new(ChordRecognizerConfig { template_kind, ..ChordRecognizerConfig::default() });
+
+            for quality in [ChordQuality::Major, ChordQuality::Minor] {
+                for root in 0..12 {
+                    let chord = Chord { root, quality };
+                    assert_eq!(recognizer.classify_frame(&chord_chroma(chord)), Some(chord), "{:?} templates", template_kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn silence_and_noise_are_no_chord() {
+        let recognizer = ChordRecognizer::new(ChordRecognizerConfig::default());
+        assert_eq!(recognizer.classify_frame(&[0.0; 12]), None);
+        assert_eq!(recognizer.classify_frame(&[1.0; 12]), None);
+    }
+
+    #[test]
+    fn viterbi_smooths_over_a_single_frame() {
+        let recognizer = ChordRecognizer::new(ChordRecognizerConfig::default());
+        let segments = recognizer.viterbi(&progression(), HOP_SECONDS);
+
+        assert_eq!(segments, [
+            ChordSegment { chord: Some(C_MAJOR), start: 0.0, end: 3.5 },
+            ChordSegment { chord: Some(F_MAJOR), start: 3.5, end: 6.5 },
+        ]);
+    }
+
+    #[test]
+    fn online_tracker_follows_the_progression_after_its_lag() {
+        let config = ChordRecognizerConfig::default();
+        let lag_frames = config.lag_frames;
+        let mut tracker = OnlineChordTracker::new(config);
+
+        let mut finished = Vec::new();
+        for (i, frame) in progression().iter().enumerate() {
+            let time = i as f32 * HOP_SECONDS;
+            finished.extend(tracker.push_frame(frame, time));
+
+            // Nothing is decided until the lag has passed
+            if i < lag_frames {
+                assert_eq!(tracker.current_segment(), None);
+            }
+        }
+
+        assert_eq!(finished, [ChordSegment { chord: Some(C_MAJOR), start: 0.0, end: 3.5 }]);
+
+        // The last decision is for the frame lag_frames before the newest one
+        let last_decided = progression().len() - 1 - lag_frames;
+        assert_eq!(tracker.current_segment(), Some(ChordSegment {
+            chord: Some(F_MAJOR),
+            start: 3.5,
+            end: (last_decided + 1) as f32 * HOP_SECONDS,
+        }));
+    }
+
+    #[test]
+    fn online_tracker_reports_silence_as_no_chord() {
+        let mut tracker = OnlineChordTracker::new(ChordRecognizerConfig::default());
+        for i in 0..5 {
+            tracker.push_frame(&[0.0; 12], i as f32 * HOP_SECONDS);
+        }
+        assert_eq!(tracker.current_segment().map(|segment| segment.chord), Some(None));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::SpectrumData;
+use crate::note_detection::frequency_to_midi;
+
+// Settings for folding a magnitude spectrum into pitch classes
+#[derive(Debug, Clone)]
+pub struct ChromaConfig {
+    // Number of chroma bins per octave. Either 12 (one per semitone) or 36 (three per semitone)
+    pub bins_per_octave: usize,
+
+    // Frequencies outside of this range are ignored when building the chroma vector
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+
+    // Gamma value for the log compression log(1 + gamma * x), where x is the magnitude relative to
+    // a full-scale sine. Higher values flatten the dynamics
+    pub compression_factor: f32,
+
+    // Estimate the tuning of the input from the spectrum instead of assuming A4 = 440 Hz
+    pub estimate_tuning: bool,
+}
+
+impl Default for ChromaConfig {
+    fn default() -> Self {
+        Self {
+            bins_per_octave: 12,
+            min_frequency: 55.0,
+            max_frequency: 5000.0,
+            compression_factor: 100.0,
+            estimate_tuning: true,
+        }
+    }
+}
+
+// Converts a frequency into a fractional MIDI pitch, shifted by the tuning offset in semitones
+pub fn frequency_to_pitch(frequency: f32, tuning_offset: f32) -> f32 {
+    frequency_to_midi(frequency) - tuning_offset
+}
+
+// Estimates how far the input is tuned away from A4 = 440 Hz, in semitones within [-0.5, 0.5).
+// Every local maximum in range votes for its deviation from the nearest equal-tempered pitch,
+// weighted by its power, so that the loudest partials decide. The votes are averaged on the unit circle so that deviations of
+// +0.49 and -0.49 semitones are treated as neighbours.
+pub fn estimate_tuning(spectrum: &[SpectrumData], min_frequency: f32, max_frequency: f32) -> f32 {
+    let mut sum_sin = 0.0;
+    let mut sum_cos = 0.0;
+
+    // The bins are in order of frequency, so the range is found without visiting the others
+    let (first, end) = frequency_range(spectrum, min_frequency, max_frequency);
+    for i in first.max(1)..end.min(spectrum.len().saturating_sub(1)) {
+        let bin = &spectrum[i];
+        if bin.power <= spectrum[i - 1].power || bin.power <= spectrum[i + 1].power {
+            continue;
+        }
+
+        let pitch = frequency_to_pitch(bin.frequency, 0.0);
+        let deviation = pitch - pitch.round();
+        let angle = 2.0 * std::f32::consts::PI * deviation;
+
+        sum_sin += bin.power * angle.sin();
+        sum_cos += bin.power * angle.cos();
+    }
+
+    if sum_sin == 0.0 && sum_cos == 0.0 {
+        return 0.0;
+    }
+
+    let offset = sum_sin.atan2(sum_cos) / (2.0 * std::f32::consts::PI);
+
+    // atan2 returns [-pi, pi], so only the upper edge needs wrapping
+    if offset >= 0.5 {
+        offset - 1.0
+    } else {
+        offset
+    }
+}
+
+// Folds the spectrum into `chroma.len()` pitch-class bins, starting at C. Each bin accumulates the
+// power of every spectrum bin that maps onto it, and the root of the sum is taken as its
+// magnitude, so the square root is taken once per chroma bin rather than per spectrum bin. The
+// magnitudes are scaled so that `full_scale` (the magnitude of a full-scale sine) becomes 1.0, log
+// compressed and normalized so that the largest value is 1.0. Without the scaling, the compression
+// would see raw FFT magnitudes in the thousands and flatten every chord into noise.
+pub fn compute_chroma(spectrum: &[SpectrumData], config: &ChromaConfig, tuning_offset: f32, full_scale: f32, chroma: &mut [f32]) {
+    chroma.iter_mut().for_each(|value| *value = 0.0);
+
+    let bins_per_octave = chroma.len();
+    if bins_per_octave == 0 {
+        return;
+    }
+    let bins_per_semitone = bins_per_octave as f32 / 12.0;
+
+    let (first, end) = frequency_range(spectrum, config.min_frequency, config.max_frequency);
+    for bin in &spectrum[first..end] {
+        let pitch = frequency_to_pitch(bin.frequency, tuning_offset);
+        let chroma_index = (pitch * bins_per_semitone).round() as i64;
+        let chroma_index = chroma_index.rem_euclid(bins_per_octave as i64) as usize;
+
+        chroma[chroma_index] += bin.power;
+    }
+
+    let scale = config.compression_factor / full_scale.max(f32::MIN_POSITIVE);
+    for value in chroma.iter_mut() {
+        *value = (1.0 + scale * value.sqrt()).ln();
+    }
+
+    let max_value = chroma.iter().cloned().fold(0.0, f32::max);
+    if max_value > 0.0 {
+        chroma.iter_mut().for_each(|value| *value /= max_value);
+    }
+}
+
+// First and end index of the bins between min_frequency and max_frequency, for a spectrum in order
+// of frequency
+fn frequency_range(spectrum: &[SpectrumData], min_frequency: f32, max_frequency: f32) -> (usize, usize) {
+    let first = spectrum.partition_point(|bin| bin.frequency < min_frequency);
+    let end = spectrum.partition_point(|bin| bin.frequency <= max_frequency).max(first);
+    (first, end)
+}
+
+// Reduces a 36-bin chroma vector to 12 semitone bins by summing each semitone's centre bin with
+// its two neighbours. A 12-bin input is copied as is.
+pub fn fold_to_semitones(chroma: &[f32], semitones: &mut [f32; 12]) {
+    if chroma.len() == 12 {
+        semitones.copy_from_slice(chroma);
+        return;
+    }
+
+    let bins_per_semitone = chroma.len() / 12;
+    let half_width = bins_per_semitone / 2;
+
+    for (pitch_class, value) in semitones.iter_mut().enumerate() {
+        let center = pitch_class * bins_per_semitone;
+        *value = (0..bins_per_semitone)
+            .map(|offset| chroma[(center + chroma.len() + offset - half_width) % chroma.len()])
+            .sum();
+    }
+
+    let max_value = semitones.iter().cloned().fold(0.0, f32::max);
+    if max_value > 0.0 {
+        semitones.iter_mut().for_each(|value| *value /= max_value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIN_WIDTH: f32 = 0.5;
+    const NUM_BINS: usize = 12_000;
+
+    // Pitch classes that are not in a chord
+    fn other_pitch_classes(chord: &[usize]) -> impl Iterator<Item = usize> + '_ {
+        (0..12).filter(move |pitch_class| !chord.contains(pitch_class))
+    }
+
+    // Spectrum of sines at `amplitude` relative to full scale, shaped like a Hann-windowed FFT: a
+    // main lobe of three bins over a low noise floor
+    fn spectrum(frequencies: &[f32], amplitude: f32, full_scale: f32) -> Vec<SpectrumData> {
+        let noise = (1e-5 * full_scale).powi(2);
+        let mut spectrum: Vec<SpectrumData> = (0..NUM_BINS)
+            .map(|i| SpectrumData { frequency: i as f32 * BIN_WIDTH, power: noise })
+            .collect();
+
+        for frequency in frequencies {
+            let bin = (frequency / BIN_WIDTH).round() as usize;
+            let peak = (amplitude * full_scale).powi(2);
+            spectrum[bin].power += peak;
+            spectrum[bin - 1].power += peak / 4.0;
+            spectrum[bin + 1].power += peak / 4.0;
+        }
+
+        spectrum
+    }
+
+    fn chroma(spectrum: &[SpectrumData], tuning_offset: f32, full_scale: f32) -> Vec<f32> {
+        let mut chroma = vec![0.0; 12];
+        compute_chroma(spectrum, &ChromaConfig::default(), tuning_offset, full_scale, &mut chroma);
+        chroma
+    }
+
+    // C4, E4 and G4 with a detuning in semitones
+    fn c_major(detune: f32) -> Vec<f32> {
+        [60.0, 64.0, 67.0].iter().map(|midi: &f32| 440.0 * ((midi + detune - 69.0) / 12.0).exp2()).collect()
+    }
+
+    #[test]
+    fn chord_tones_stand_out_of_the_chroma() {
+        let chroma = chroma(&spectrum(&c_major(0.0), 0.1, 1000.0), 0.0, 1000.0);
+
+        for pitch_class in [0, 4, 7] {
+            assert!(chroma[pitch_class] > 0.95, "{:?}", chroma);
+        }
+        for pitch_class in other_pitch_classes(&[0, 4, 7]) {
+            assert!(chroma[pitch_class] < 0.05, "{:?}", chroma);
+        }
+    }
+
+    #[test]
+    fn chroma_does_not_depend_on_the_fft_scale() {
+        let small = chroma(&spectrum(&c_major(0.0), 0.1, 1000.0), 0.0, 1000.0);
+        let large = chroma(&spectrum(&c_major(0.0), 0.1, 16384.0), 0.0, 16384.0);
+
+        for (small, large) in small.iter().zip(large.iter()) {
+            assert!((small - large).abs() < 1e-3, "{:?} and {:?}", small, large);
+        }
+    }
+
+    #[test]
+    fn silence_has_an_empty_chroma() {
+        let silence: Vec<SpectrumData> = (0..NUM_BINS)
+            .map(|i| SpectrumData { frequency: i as f32 * BIN_WIDTH, power: 0.0 })
+            .collect();
+        assert!(chroma(&silence, 0.0, 1000.0).iter().all(|&value| value == 0.0));
+    }
+
+    #[test]
+    fn tuning_is_estimated_from_the_peaks() {
+        for detune in [-0.3, 0.0, 0.2, 0.45] {
+            let spectrum = spectrum(&c_major(detune), 0.1, 1000.0);
+            let offset = estimate_tuning(&spectrum, 55.0, 5000.0);
+            assert!((offset - detune).abs() < 0.03, "{} estimated as {}", detune, offset);
+        }
+    }
+
+    #[test]
+    fn the_tuning_offset_moves_detuned_notes_back() {
+        // 30 cents sharp puts every tone into the bin a third of a semitone up
+        let spectrum = spectrum(&c_major(0.3), 0.1, 1000.0);
+        let config = ChromaConfig { bins_per_octave: 36, ..ChromaConfig::default() };
+        let mut chroma = vec![0.0; 36];
+
+        compute_chroma(&spectrum, &config, 0.0, 1000.0, &mut chroma);
+        assert!([1, 13, 22].iter().all(|&bin| chroma[bin] > 0.95), "{:?}", chroma);
+
+        let offset = estimate_tuning(&spectrum, 55.0, 5000.0);
+        compute_chroma(&spectrum, &config, offset, 1000.0, &mut chroma);
+        assert!([0, 12, 21].iter().all(|&bin| chroma[bin] > 0.95), "{:?}", chroma);
+    }
+
+    #[test]
+    fn folding_sums_the_bins_around_each_semitone() {
+        let mut chroma = [0.0; 36];
+        chroma[35] = 0.5;
+        chroma[0] = 1.0;
+        chroma[1] = 0.5;
+        chroma[12] = 1.0;
+
+        let mut semitones = [0.0; 12];
+        fold_to_semitones(&chroma, &mut semitones);
+
+        assert_eq!(semitones[0], 1.0);
+        assert_eq!(semitones[4], 0.5);
+        assert!(semitones.iter().enumerate().all(|(pitch_class, &value)| value == 0.0 || [0, 4].contains(&pitch_class)));
+    }
+}
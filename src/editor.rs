@@ -0,0 +1,261 @@
+use nih_plug_egui::egui;
+use crate::analysis_frame::{snapshot_bin_frequency, AnalysisFrame, SPECTRUM_SNAPSHOT_BINS};
+use crate::logging::LogLevel;
+use crate::note_naming::NoteNaming;
+
+// Height of the spectrum display in points
+const SPECTRUM_HEIGHT: f32 = 120.0;
+
+// Level range shown by the spectrum display
+const SPECTRUM_MIN_DB: f32 = -100.0;
+const SPECTRUM_MAX_DB: f32 = 0.0;
+
+// Height of the spectrogram in points, and the width of its note-name axis
+const SPECTROGRAM_HEIGHT: f32 = 160.0;
+const SPECTROGRAM_AXIS_WIDTH: f32 = 36.0;
+
+// Number of columns the spectrogram keeps. One column arrives per analysis run, roughly every
+// 46 ms, so this shows about twelve seconds.
+const SPECTROGRAM_COLUMNS: usize = 256;
+
+// Colour map from quiet to loud, evenly spaced over the level range
+const SPECTROGRAM_COLOURS: [[u8; 3]; 5] = [
+    [0, 0, 4],
+    [87, 16, 110],
+    [188, 55, 84],
+    [249, 142, 9],
+    [252, 255, 164],
+];
+
+// Scrolling spectrogram of the spectrogram columns published by the analysis, with the notes that
+// were sounding at the time of each column. Stored as a texture that is used as a circular buffer,
+// so adding a column only changes one column of pixels.
+pub struct Spectrogram {
+    // Pixels of every column, with the highest frequency in the top row
+    image: egui::ColorImage,
+
+    // Sounding notes of every column as a bit per MIDI note
+    notes: Vec<u128>,
+
+    // Column written last, and the analysis index it came from
+    newest_column: usize,
+    last_index: u64,
+
+    // Frequency range of the stored columns, in Hz
+    min_frequency: f32,
+    max_frequency: f32,
+
+    // Texture the image is drawn from, created on first draw. Columns added since the last draw
+    // are uploaded on their own; the whole image only after a clear.
+    texture: Option<egui::TextureHandle>,
+    texture_dirty: bool,
+    new_columns: usize,
+}
+
+impl Default for Spectrogram {
+    fn default() -> Self {
+        Self {
+            image: egui::ColorImage::new([SPECTROGRAM_COLUMNS, SPECTRUM_SNAPSHOT_BINS], level_colour(SPECTRUM_MIN_DB)),
+            notes: vec![0; SPECTROGRAM_COLUMNS],
+            newest_column: SPECTROGRAM_COLUMNS - 1,
+            last_index: 0,
+            min_frequency: 0.0,
+            max_frequency: 0.0,
+            texture: None,
+            texture_dirty: true,
+            new_columns: 0,
+        }
+    }
+}
+
+impl Spectrogram {
+    // Adds the frame's spectrogram column if it has not been added yet. Columns from another
+    // frequency range no longer line up with the axis, so a range change clears the history.
+    pub fn update(&mut self, frame: &AnalysisFrame) {
+        if frame.spectrogram_index == self.last_index {
+            return;
+        }
+        self.last_index = frame.spectrogram_index;
+
+        if frame.min_frequency != self.min_frequency || frame.max_frequency != self.max_frequency {
+            self.min_frequency = frame.min_frequency;
+            self.max_frequency = frame.max_frequency;
+            self.clear();
+        }
+
+        self.newest_column = (self.newest_column + 1) % SPECTROGRAM_COLUMNS;
+        for (bin, &level_db) in frame.spectrogram_column.iter().enumerate() {
+            let row = SPECTRUM_SNAPSHOT_BINS - 1 - bin;
+            self.image[(self.newest_column, row)] = level_colour(level_db);
+        }
+        self.notes[self.newest_column] = frame
+            .notes()
+            .iter()
+            .fold(0, |notes, note| notes | 1u128.checked_shl(note.midi as u32).unwrap_or(0));
+        self.new_columns = (self.new_columns + 1).min(SPECTROGRAM_COLUMNS);
+    }
+
+    pub fn clear(&mut self) {
+        self.image.pixels.iter_mut().for_each(|pixel| *pixel = level_colour(SPECTRUM_MIN_DB));
+        self.notes.iter_mut().for_each(|notes| *notes = 0);
+        self.texture_dirty = true;
+    }
+}
+
+// Draws the spectrum snapshot as a line over the full available width, labelled with the
+// frequency range it covers. The snapshot bins are already log-spaced, so they are drawn at equal
+// distances. Hovering shows the frequency and level of the bin under the pointer.
+pub fn draw_spectrum(ui: &mut egui::Ui, spectrum: &[f32; SPECTRUM_SNAPSHOT_BINS], min_frequency: f32, max_frequency: f32) {
+    let desired_size = egui::vec2(ui.available_width(), SPECTRUM_HEIGHT);
+    let (rect, response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+
+    let points: Vec<egui::Pos2> = spectrum
+        .iter()
+        .enumerate()
+        .map(|(i, &level_db)| {
+            let x = rect.left() + rect.width() * i as f32 / (SPECTRUM_SNAPSHOT_BINS - 1) as f32;
+            let normalized = ((level_db - SPECTRUM_MIN_DB) / (SPECTRUM_MAX_DB - SPECTRUM_MIN_DB)).clamp(0.0, 1.0);
+            egui::pos2(x, rect.bottom() - rect.height() * normalized)
+        })
+        .collect();
+
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, egui::Color32::LIGHT_BLUE)));
+
+    let font = egui::FontId::proportional(11.0);
+    painter.text(rect.left_bottom() + egui::vec2(4.0, -2.0), egui::Align2::LEFT_BOTTOM,
+                 format_frequency(min_frequency), font.clone(), egui::Color32::GRAY);
+    painter.text(rect.right_bottom() + egui::vec2(-4.0, -2.0), egui::Align2::RIGHT_BOTTOM,
+                 format_frequency(max_frequency), font.clone(), egui::Color32::GRAY);
+
+    if let Some(pointer) = response.hover_pos() {
+        let position = ((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+        let bin = (position * (SPECTRUM_SNAPSHOT_BINS - 1) as f32).round() as usize;
+        let x = rect.left() + rect.width() * bin as f32 / (SPECTRUM_SNAPSHOT_BINS - 1) as f32;
+        painter.vline(x, rect.y_range(), egui::Stroke::new(0.5, egui::Color32::from_white_alpha(80)));
+        painter.text(rect.center_top() + egui::vec2(0.0, 2.0), egui::Align2::CENTER_TOP,
+                     format!("{}  {:.1} dB", format_frequency(snapshot_bin_frequency(bin, min_frequency, max_frequency)), spectrum[bin]),
+                     font, egui::Color32::WHITE);
+    }
+}
+
+// Frequency label in Hz, or in kHz from 1 kHz up
+fn format_frequency(frequency: f32) -> String {
+    if frequency < 1000.0 {
+        format!("{:.0} Hz", frequency)
+    } else {
+        format!("{:.1} kHz", frequency / 1000.0)
+    }
+}
+
+// Draws the spectrogram over the full available width, scrolling from right to left, with a
+// note-name axis on the left and the detected notes marked over the columns they sounded in
+pub fn draw_spectrogram(ui: &mut egui::Ui, spectrogram: &mut Spectrogram, naming: &NoteNaming) {
+    match &mut spectrogram.texture {
+        Some(texture) if !spectrogram.texture_dirty => {
+            for age in (0..spectrogram.new_columns).rev() {
+                let column = (spectrogram.newest_column + SPECTROGRAM_COLUMNS - age) % SPECTROGRAM_COLUMNS;
+                let pixels = (0..SPECTRUM_SNAPSHOT_BINS).map(|row| spectrogram.image[(column, row)]).collect();
+                let column_image = egui::ColorImage { size: [1, SPECTRUM_SNAPSHOT_BINS], pixels };
+                texture.set_partial([column, 0], column_image, egui::TextureOptions::LINEAR);
+            }
+        }
+        Some(texture) => texture.set(spectrogram.image.clone(), egui::TextureOptions::LINEAR),
+        None => {
+            let image = spectrogram.image.clone();
+            spectrogram.texture = Some(ui.ctx().load_texture("spectrogram", image, egui::TextureOptions::LINEAR));
+        }
+    }
+    spectrogram.texture_dirty = false;
+    spectrogram.new_columns = 0;
+
+    let desired_size = egui::vec2(ui.available_width(), SPECTROGRAM_HEIGHT);
+    let (rect, _response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let plot = egui::Rect::from_min_max(rect.left_top() + egui::vec2(SPECTROGRAM_AXIS_WIDTH, 0.0), rect.right_bottom());
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+
+    // The oldest column follows the newest one in the circular buffer. It is drawn on the left,
+    // up to the end of the texture, and the rest of the texture continues from there.
+    if let Some(texture) = &spectrogram.texture {
+        let oldest_column = (spectrogram.newest_column + 1) % SPECTROGRAM_COLUMNS;
+        let split = (SPECTROGRAM_COLUMNS - oldest_column) as f32 / SPECTROGRAM_COLUMNS as f32;
+        let split_x = plot.left() + plot.width() * split;
+        let oldest_u = oldest_column as f32 / SPECTROGRAM_COLUMNS as f32;
+
+        painter.image(texture.id(),
+                      egui::Rect::from_min_max(plot.left_top(), egui::pos2(split_x, plot.bottom())),
+                      egui::Rect::from_min_max(egui::pos2(oldest_u, 0.0), egui::pos2(1.0, 1.0)),
+                      egui::Color32::WHITE);
+        painter.image(texture.id(),
+                      egui::Rect::from_min_max(egui::pos2(split_x, plot.top()), plot.right_bottom()),
+                      egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(oldest_u, 1.0)),
+                      egui::Color32::WHITE);
+    }
+
+    let octaves = (spectrogram.max_frequency / spectrogram.min_frequency).log2();
+    if octaves.is_nan() || octaves <= 0.0 {
+        return;
+    }
+
+    // Height of a MIDI note, or None if it lies outside the range shown
+    let note_y = |midi_note: usize| {
+        let frequency = 440.0 * 2.0f32.powf((midi_note as f32 - 69.0) / 12.0);
+        let position = (frequency / spectrogram.min_frequency).log2() / octaves;
+        (0.0..=1.0).contains(&position).then(|| plot.bottom() - plot.height() * position)
+    };
+
+    // Note-name axis: every C, or every major third when fewer than three octaves are shown
+    let step = if octaves < 3.0 { 4 } else { 12 };
+    let font = egui::FontId::proportional(10.0);
+    for midi_note in (0..128).step_by(step) {
+        if let Some(y) = note_y(midi_note) {
+            painter.hline(plot.left()..=plot.right(), y, egui::Stroke::new(0.5, egui::Color32::from_white_alpha(40)));
+            painter.text(egui::pos2(rect.left() + 4.0, y), egui::Align2::LEFT_CENTER,
+                         naming.note_name(midi_note), font.clone(), egui::Color32::GRAY);
+        }
+    }
+
+    // Detected notes, as a short bar in each column they were sounding in
+    let column_width = plot.width() / SPECTROGRAM_COLUMNS as f32;
+    for age in 0..SPECTROGRAM_COLUMNS {
+        let column = (spectrogram.newest_column + SPECTROGRAM_COLUMNS - age) % SPECTROGRAM_COLUMNS;
+        let notes = spectrogram.notes[column];
+        if notes == 0 {
+            continue;
+        }
+
+        let x = plot.right() - column_width * (age + 1) as f32;
+        for midi_note in (0..128).filter(|&midi_note| notes & (1u128 << midi_note) != 0) {
+            if let Some(y) = note_y(midi_note) {
+                let marker = egui::Rect::from_min_size(egui::pos2(x, y - 1.0), egui::vec2(column_width.max(1.0), 2.0));
+                painter.rect_filled(marker, 0.0, egui::Color32::from_rgb(80, 255, 160));
+            }
+        }
+    }
+}
+
+// Colour of a level in the spectrogram
+fn level_colour(level_db: f32) -> egui::Color32 {
+    let normalized = ((level_db - SPECTRUM_MIN_DB) / (SPECTRUM_MAX_DB - SPECTRUM_MIN_DB)).clamp(0.0, 1.0);
+    let position = normalized * (SPECTROGRAM_COLOURS.len() - 1) as f32;
+    let index = (position as usize).min(SPECTROGRAM_COLOURS.len() - 2);
+    let fraction = position - index as f32;
+
+    let (low, high) = (SPECTROGRAM_COLOURS[index], SPECTROGRAM_COLOURS[index + 1]);
+    let channel = |i: usize| (low[i] as f32 + (high[i] as f32 - low[i] as f32) * fraction).round() as u8;
+    egui::Color32::from_rgb(channel(0), channel(1), channel(2))
+}
+
+// Text colour of a log record in the diagnostics panel
+pub fn log_level_colour(level: LogLevel) -> egui::Color32 {
+    match level {
+        LogLevel::Debug => egui::Color32::GRAY,
+        LogLevel::Info => egui::Color32::LIGHT_GRAY,
+        LogLevel::Warning => egui::Color32::YELLOW,
+        LogLevel::Error => egui::Color32::LIGHT_RED,
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+// Highest sample rate the analysis accepts, in Hz. Well above any real host, but low enough that
+// the buffers sized from it stay reasonable.
+pub const MAX_SAMPLE_RATE: f32 = 1_536_000.0;
+
+// Reasons the analysis cannot process its input. Returned instead of panicking, so that a host
+// feeding invalid values cannot take the plugin, and with it the DAW, down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalysisError {
+    // The sample rate is not a finite number between 0 and MAX_SAMPLE_RATE
+    InvalidSampleRate(f32),
+
+    // A frame holds NaN or infinite samples
+    NonFiniteInput,
+
+    // A frame is shorter than the estimator can work with
+    FrameTooShort { required: usize, actual: usize },
+
+    // Two buffers that have to match in length do not
+    LengthMismatch { expected: usize, actual: usize },
+
+    // The analysis ran before it was initialized for a sample rate
+    NotInitialized,
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalysisError::InvalidSampleRate(sample_rate) => write!(f, "invalid sample rate: {} Hz", sample_rate),
+            AnalysisError::NonFiniteInput => write!(f, "input contains NaN or infinite samples"),
+            AnalysisError::FrameTooShort { required, actual } => {
+                write!(f, "frame of {} samples is shorter than the required {}", actual, required)
+            }
+            AnalysisError::LengthMismatch { expected, actual } => {
+                write!(f, "buffer of {} samples where {} were expected", actual, expected)
+            }
+            AnalysisError::NotInitialized => write!(f, "analysis has not been initialized"),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+// Checks that a sample rate can be used to size the analysis
+pub fn check_sample_rate(sample_rate: f32) -> Result<(), AnalysisError> {
+    if sample_rate.is_finite() && sample_rate > 0.0 && sample_rate <= MAX_SAMPLE_RATE {
+        Ok(())
+    } else {
+        Err(AnalysisError::InvalidSampleRate(sample_rate))
+    }
+}
+
+// Checks that every sample of a frame is a finite number
+pub fn check_finite(frame: &[f32]) -> Result<(), AnalysisError> {
+    if frame.iter().all(|sample| sample.is_finite()) {
+        Ok(())
+    } else {
+        Err(AnalysisError::NonFiniteInput)
+    }
+}
@@ -0,0 +1,310 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// Settings for harmonic/percussive separation
+#[derive(Debug, Clone)]
+pub struct HpssConfig {
+    // Length of each STFT frame in milliseconds, zero-padded to the next power of two for the FFT
+    pub frame_length_ms: f32,
+
+    // Frames per frame length. Four gives 75% overlap.
+    pub overlap: usize,
+
+    // Median filter lengths: across frames for the harmonic part and across bins for the
+    // percussive part. Both are rounded up to an odd number.
+    pub harmonic_frames: usize,
+    pub percussive_bins: usize,
+
+    // Exponent of the soft mask. Higher values separate harder; 2 is the Wiener-like mask.
+    pub mask_power: f32,
+}
+
+impl Default for HpssConfig {
+    fn default() -> Self {
+        Self {
+            // 4092 samples in a 4096 point FFT at 44.1 kHz
+            frame_length_ms: 92.8,
+            overlap: 4,
+            harmonic_frames: 17,
+            percussive_bins: 17,
+            mask_power: 2.0,
+        }
+    }
+}
+
+// Streaming harmonic/percussive separation by median filtering (FitzGerald 2010). Sustained
+// partials are smooth over time and drums are smooth over frequency, so a median across frames
+// estimates the harmonic magnitude of every bin and a median across bins the percussive one. The
+// harmonic part is resynthesised by overlap-add and returned sample by sample, delayed by
+// latency() samples. Nothing is allocated after creation.
+pub struct HarmonicSeparator {
+    config: HpssConfig,
+    frame_size: usize,
+    hop_size: usize,
+
+    // Power-of-two FFT size the frames are zero-padded to
+    fft_size: usize,
+
+    forward_fft: Arc<dyn Fft<f32>>,
+    inverse_fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+
+    // Scale that undoes the analysis and synthesis windows and the unnormalized inverse FFT
+    normalization: f32,
+
+    // Circular buffer holding the most recent frame_size samples
+    input: Vec<f32>,
+    input_position: usize,
+    samples_until_hop: usize,
+
+    // Spectra and magnitudes of the most recent harmonic_frames frames, as a circular buffer
+    spectra: Vec<Vec<Complex<f32>>>,
+    magnitudes: Vec<Vec<f32>>,
+    newest_frame: usize,
+
+    // Scratch spectrum for the frame being resynthesised, and space for the medians
+    spectrum: Vec<Complex<f32>>,
+    median_scratch: Vec<f32>,
+
+    // Overlap-add accumulator, and the finished samples handed out until the next hop
+    overlap_add: Vec<f32>,
+    output: Vec<f32>,
+    output_position: usize,
+}
+
+impl HarmonicSeparator {
+    pub fn new(config: HpssConfig, sample_rate: f32) -> Self {
+        let frame_size = ((config.frame_length_ms * sample_rate / 1000.0).round() as usize).max(4);
+        let hop_size = (frame_size / config.overlap.max(1)).max(1);
+        let fft_size = frame_size.next_power_of_two();
+        let num_bins = fft_size / 2 + 1;
+        let harmonic_frames = config.harmonic_frames.max(1) | 1;
+        let percussive_bins = config.percussive_bins.max(1) | 1;
+
+        let mut planner = FftPlanner::new();
+        let forward_fft = planner.plan_fft_forward(fft_size);
+        let inverse_fft = planner.plan_fft_inverse(fft_size);
+
+        // Periodic Hann, used for analysis and synthesis
+        let window: Vec<f32> = (0..frame_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / frame_size as f32).cos())
+            .collect();
+
+        // Overlapping squared windows sum to a constant; dividing by it restores unity gain
+        let window_sum = (0..hop_size)
+            .map(|i| (i..frame_size).step_by(hop_size).map(|j| window[j] * window[j]).sum::<f32>())
+            .sum::<f32>() / hop_size as f32;
+
+        Self {
+            frame_size,
+            hop_size,
+            fft_size,
+            forward_fft,
+            inverse_fft,
+            window,
+            normalization: 1.0 / (window_sum.max(f32::EPSILON) * fft_size as f32),
+            input: vec![0.0; frame_size],
+            input_position: 0,
+            samples_until_hop: hop_size,
+            spectra: vec![vec![Complex { re: 0.0, im: 0.0 }; fft_size]; harmonic_frames],
+            magnitudes: vec![vec![0.0; num_bins]; harmonic_frames],
+            newest_frame: 0,
+            spectrum: vec![Complex { re: 0.0, im: 0.0 }; fft_size],
+            median_scratch: Vec::with_capacity(harmonic_frames.max(percussive_bins)),
+            overlap_add: vec![0.0; frame_size],
+            output: vec![0.0; hop_size],
+            output_position: 0,
+            config: HpssConfig { harmonic_frames, percussive_bins, ..config },
+        }
+    }
+
+    // Delay between a sample going in and its harmonic part coming out, in samples. The output
+    // trails the centre frame of the history, which starts frame_size - 1 samples before the
+    // newest one ends.
+    pub fn latency(&self) -> usize {
+        self.frame_size - 1 + self.config.harmonic_frames / 2 * self.hop_size
+    }
+
+    // Clears all history without reallocating
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|sample| *sample = 0.0);
+        self.input_position = 0;
+        self.samples_until_hop = self.hop_size;
+        self.spectra.iter_mut().flatten().for_each(|bin| *bin = Complex { re: 0.0, im: 0.0 });
+        self.magnitudes.iter_mut().flatten().for_each(|magnitude| *magnitude = 0.0);
+        self.newest_frame = 0;
+        self.overlap_add.iter_mut().for_each(|sample| *sample = 0.0);
+        self.output.iter_mut().for_each(|sample| *sample = 0.0);
+        self.output_position = 0;
+    }
+
+    // Adds one sample and returns the harmonic part of the sample latency() samples ago
+    pub fn process_sample(&mut self, sample: f32) -> f32 {
+        self.process(sample, true)
+    }
+
+    // Without masking, frames are only transformed and overlap-added back, which returns the
+    // input delayed by latency() samples
+    fn process(&mut self, sample: f32, masked: bool) -> f32 {
+        self.input[self.input_position] = sample;
+        self.input_position = (self.input_position + 1) % self.frame_size;
+
+        self.samples_until_hop -= 1;
+        if self.samples_until_hop == 0 {
+            self.samples_until_hop = self.hop_size;
+            self.analyse_frame();
+            self.resynthesise_centre_frame(masked);
+        }
+
+        let output = self.output[self.output_position];
+        self.output_position = (self.output_position + 1).min(self.hop_size - 1);
+        output
+    }
+
+    // Windows and transforms the newest frame into the spectrum history
+    fn analyse_frame(&mut self) {
+        self.newest_frame = (self.newest_frame + 1) % self.spectra.len();
+        let spectrum = &mut self.spectra[self.newest_frame];
+
+        // input_position points at the oldest sample in the circular buffer. The FFT overwrites its
+        // buffer, so the padding is cleared every frame.
+        let (frame, padding) = spectrum.split_at_mut(self.frame_size);
+        for (i, bin) in frame.iter_mut().enumerate() {
+            let sample = self.input[(self.input_position + i) % self.frame_size];
+            *bin = Complex { re: sample * self.window[i], im: 0.0 };
+        }
+        padding.iter_mut().for_each(|bin| *bin = Complex { re: 0.0, im: 0.0 });
+        self.forward_fft.process(spectrum);
+
+        for (magnitude, bin) in self.magnitudes[self.newest_frame].iter_mut().zip(spectrum.iter()) {
+            *magnitude = bin.norm();
+        }
+    }
+
+    // Masks the frame in the middle of the history, whose neighbours in time are all known, and
+    // overlap-adds it into the output. An unmasked frame is added back as it is.
+    fn resynthesise_centre_frame(&mut self, masked: bool) {
+        let num_frames = self.spectra.len();
+        let centre = (self.newest_frame + num_frames - num_frames / 2) % num_frames;
+
+        self.spectrum.copy_from_slice(&self.spectra[centre]);
+        if masked {
+            self.apply_harmonic_mask(centre);
+        }
+
+        self.inverse_fft.process(&mut self.spectrum);
+
+        // Only the frame itself is overlap-added; the synthesis window ends where the padding starts
+        for ((sum, bin), window) in self.overlap_add.iter_mut().zip(self.spectrum.iter()).zip(self.window.iter()) {
+            *sum += bin.re * window * self.normalization;
+        }
+
+        // The first hop is complete: no later frame reaches back that far
+        self.output.copy_from_slice(&self.overlap_add[..self.hop_size]);
+        self.output_position = 0;
+        self.overlap_add.copy_within(self.hop_size.., 0);
+        let tail = self.frame_size - self.hop_size;
+        self.overlap_add[tail..].iter_mut().for_each(|sample| *sample = 0.0);
+    }
+
+    // Scales every bin of the centre frame, copied to the scratch spectrum, by its share of
+    // harmonic energy
+    fn apply_harmonic_mask(&mut self, centre: usize) {
+        let num_bins = self.fft_size / 2 + 1;
+        let half_width = self.config.percussive_bins / 2;
+
+        for bin in 0..num_bins {
+            // Harmonic estimate: median of this bin across frames
+            self.median_scratch.clear();
+            self.median_scratch.extend(self.magnitudes.iter().map(|magnitudes| magnitudes[bin]));
+            let harmonic = median(&mut self.median_scratch);
+
+            // Percussive estimate: median of the centre frame across neighbouring bins
+            let centre_magnitudes = &self.magnitudes[centre];
+            self.median_scratch.clear();
+            self.median_scratch.extend_from_slice(
+                &centre_magnitudes[bin.saturating_sub(half_width)..(bin + half_width + 1).min(num_bins)],
+            );
+            let percussive = median(&mut self.median_scratch);
+
+            let harmonic_power = harmonic.powf(self.config.mask_power);
+            let total_power = harmonic_power + percussive.powf(self.config.mask_power);
+            let mask = if total_power > 0.0 { harmonic_power / total_power } else { 0.0 };
+
+            // The upper half of the spectrum mirrors the lower half, so it gets the same mask
+            self.spectrum[bin] *= mask;
+            if bin > 0 && bin < self.fft_size - bin {
+                self.spectrum[self.fft_size - bin] *= mask;
+            }
+        }
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    let middle = values.len() / 2;
+    let (_, median, _) = values.select_nth_unstable_by(middle, f32::total_cmp);
+    *median
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn sine(i: usize) -> f32 {
+        0.3 * (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE).sin()
+    }
+
+    #[test]
+    fn unmasked_frames_rebuild_the_input() {
+        let mut separator = HarmonicSeparator::new(HpssConfig::default(), SAMPLE_RATE);
+        let latency = separator.latency();
+
+        // A sine with a click, so that every part of the frame shows up in the output
+        let input: Vec<f32> = (0..latency + 20_000).map(|i| if i == 5_000 { 1.0 } else { sine(i) }).collect();
+        let output: Vec<f32> = input.iter().map(|&sample| separator.process(sample, false)).collect();
+
+        for i in latency..input.len() {
+            assert!((output[i] - input[i - latency]).abs() < 1e-4, "sample {}: {} vs {}", i, output[i], input[i - latency]);
+        }
+    }
+
+    #[test]
+    fn clicks_are_removed_and_the_sine_is_kept() {
+        let mut separator = HarmonicSeparator::new(HpssConfig::default(), SAMPLE_RATE);
+        let latency = separator.latency();
+
+        // A click every quarter of a second on top of a steady sine
+        let click_spacing = (SAMPLE_RATE / 4.0) as usize;
+        let is_click = |i: usize| i > 0 && i.is_multiple_of(click_spacing);
+        let num_samples = latency + 3 * SAMPLE_RATE as usize;
+        let output: Vec<f32> = (0..num_samples)
+            .map(|i| separator.process_sample(sine(i) + if is_click(i) { 1.0 } else { 0.0 }))
+            .collect();
+
+        // Compare with the sine away from the start, where the history is still filling
+        let settled = latency + SAMPLE_RATE as usize;
+        let rms = |values: &mut dyn Iterator<Item = f32>| {
+            let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value * value, count + 1));
+            (sum / count as f32).sqrt()
+        };
+        let sine_rms = rms(&mut (settled..num_samples).map(|i| sine(i - latency)));
+        let output_rms = rms(&mut (settled..num_samples).map(|i| output[i]));
+        let residual_rms = rms(&mut (settled..num_samples).map(|i| output[i] - sine(i - latency)));
+
+        assert!((output_rms / sine_rms).log10().abs() * 20.0 < 1.0, "{} vs {}", output_rms, sine_rms);
+        assert!(residual_rms < 0.1 * sine_rms, "{}", residual_rms);
+
+        // What is left of each click is far below the click itself
+        for i in (settled..num_samples).filter(|&i| is_click(i - latency)) {
+            let click_residual = (i - 50..i + 50).map(|j| (output[j] - sine(j - latency)).abs()).fold(0.0, f32::max);
+            assert!(click_residual < 0.1, "click at {} left {}", i - latency, click_residual);
+        }
+    }
+}
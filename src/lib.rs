@@ -1,166 +1,356 @@
+pub mod analysis_frame;
+pub mod analyzer;
+pub mod beat_tracking;
+pub mod chord_recognition;
+mod chroma;
+mod editor;
+pub mod error;
+pub mod hpss;
+pub mod logging;
+pub mod noise_floor;
 mod note_detection;
+mod note_naming;
+mod note_tracking;
+pub mod onset_detection;
+pub mod peak_finding;
+pub mod pitch_detection;
+pub mod pitch_estimation;
+mod presets;
+pub mod sanitize;
 
-use std::cmp::Ordering;
 use nih_plug::prelude::*;
-use nih_plug_egui::{create_egui_editor, egui, EguiState};
-use rustfft::{num_complex::Complex, FftPlanner, FftNum, Fft};
-use std::collections::HashMap;
-use std::f32::consts::PI;
+use nih_plug_egui::{create_egui_editor, egui, widgets, EguiState};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use ordered_float::OrderedFloat;
-use crate::note_detection::get_note_data;
+use triple_buffer::TripleBuffer;
+use crate::analysis_frame::{block_levels, AnalysisFrame, FLOOR_DB};
+use crate::analyzer::{AnalysisTask, Analyzer, BlockInfo};
+use crate::beat_tracking::ChordGrid;
+use crate::chord_recognition::Chord;
+use crate::editor::{draw_spectrogram, draw_spectrum, log_level_colour, Spectrogram};
+use crate::logging::{LogCategory, LogCollector, LogLevel, Logger};
+use crate::note_detection::frequency_to_note;
+use crate::note_naming::NoteNaming;
+use crate::presets::{factory_presets, load_user_presets, save_user_preset, Preset};
+use crate::sanitize::sanitize_sample;
 extern crate rustfft;
 
+// Length of the queue that carries samples to the analysis task, in seconds. Holds a few seconds
+// of audio so that a briefly stalled background thread does not lose samples.
+const SAMPLE_QUEUE_SECONDS: f32 = 6.0;
+
+// Time between analysis tasks in milliseconds, 2048 samples at 44.1 kHz
+const ANALYSIS_INTERVAL_MS: f32 = 46.4;
+
+// Number of recognized chords the editor remembers for copying a progression
+const MAX_RECENT_CHORDS: usize = 64;
+
 struct FourierChords {
     params: Arc<FourierChordsParams>,
 
     // Need to get upon initialization for proper windowing and FFT
     sample_rate: f32,
 
-    // Buffer size
-    window_size: usize,
+    // Analysis state. Only the background task locks this during processing, so the audio thread
+    // never waits on the analysis.
+    analyzer: Arc<Mutex<Analyzer>>,
 
-    // Note data for real-time note detection
-    note_data: HashMap<OrderedFloat<f32>, String>,
+    // Write side of the lock-free queue that carries samples to the analyzer
+    sample_producer: rtrb::Producer<f32>,
 
-    // Vector of complex buffer values
-    complex_buffer: Vec<Complex<f32>>,
+    // Samples queued since the last analysis task was started, and the number of samples between
+    // analysis tasks at the current sample rate
+    samples_since_task: usize,
+    analysis_hop: usize,
 
-    // Starting sample vector
-    sample_vec: Vec<f32>,
+    // Set by reset so that the next process call has the analysis state cleared
+    reset_pending: bool,
 
-    // Vector for windowed values
-    windowed_values: Vec<f32>,
+    // Set by the editor so that the next process call has the chord history cleared
+    clear_history_requested: Arc<AtomicBool>,
 
-    // FFT algorithm object
-    fft_algorithm: Arc<dyn Fft<f32>>,
+    // Log records from every thread, and the log queue of the audio thread. The collector is
+    // emptied by the background task after each analysis and by the editor.
+    log: Arc<Mutex<LogCollector>>,
+    logger: Logger,
+
+    // Buffer size of the previous process call, whether the sample queue was full during it and
+    // whether its input held NaN or infinite samples, so that only changes are logged
+    last_buffer_size: usize,
+    sample_queue_full: bool,
+    invalid_input: bool,
+
+    // Read side of the triple buffer. Only ever locked by the editor on the GUI thread.
+    analysis_output: Arc<Mutex<triple_buffer::Output<AnalysisFrame>>>,
+}
 
-    // Spectrum data object
-    spectrum_data: Vec<SpectrumData>,
+#[derive(Params)]
+struct FourierChordsParams {
+    // Editor state
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
 
-    // Frequency resolution value. Equal to: Sample Rate / Buffer Size
-    frequency_resolution: f32,
+    // How notes are detected
+    #[id = "detection-mode"]
+    detection_mode: EnumParam<DetectionMode>,
 
-    // Nyquist Limit. Equal to: Sample Rate / 2
-    nyquist_limit: usize,
+    // Which input is analysed
+    #[id = "analysis-source"]
+    analysis_source: EnumParam<AnalysisSource>,
 
-    // Vector to hold local maxima
-    local_maxima: Vec<SpectrumData>,
+    // Whether one channel of each input is analysed or all of them mixed down, and which one
+    #[id = "channel-mode"]
+    channel_mode: EnumParam<ChannelMode>,
 
-    // Vector to hold prominent peaks
-    prominent_peaks: Vec<SpectrumData>,
+    #[id = "analysis-channel"]
+    analysis_channel: IntParam,
 
-    // Detected notes
-    detected_notes: Vec<String>,
+    // Frequency range notes are detected and the spectrum is shown in
+    #[id = "min-frequency"]
+    min_frequency: FloatParam,
 
-    // Magnitude threshold
-    magnitude_threshold: f32,
+    #[id = "max-frequency"]
+    max_frequency: FloatParam,
 
-    // Max identified magnitude
-    max_magnitude: f32,
+    // Level a pitch has to reach to be reported as a note
+    #[id = "note-threshold"]
+    note_threshold: FloatParam,
 
-    // Prominence threshold
-    prominence_threshold: f32,
+    // Filters drums and other percussive sounds out before detection, at the cost of extra latency
+    #[id = "suppress-percussion"]
+    suppress_percussion: BoolParam,
 
-    // General prominence value
-    prominence: f32,
+    // Detection function used to find the attacks that notes are reset on
+    #[id = "onset-method"]
+    onset_method: EnumParam<OnsetMethod>,
 
-    // String that is used to output the notes
-    notes_string_for_display: String,
+    // How notes and chords are named in the editor
+    #[id = "naming-system"]
+    naming_system: EnumParam<NamingSystem>,
 
-    // Utility index counter for overwriting sample_vec values
-    counter: usize,
+    #[id = "spelling"]
+    spelling: EnumParam<Spelling>,
 
-    // Boolean for debug printing buffer size
-    buffer_displayed: bool,
+    // Key used for key-aware spelling and Nashville numbers
+    #[id = "key"]
+    key: EnumParam<Key>,
 }
 
-#[derive(Params)]
-struct FourierChordsParams {
-    // Editor state
-    #[persist = "editor-state"]
-    editor_state: Arc<EguiState>,
+// Note detection methods, each backed by a pitch estimator. Polyphonic mode picks peaks from the
+// FFT spectrum; the monophonic modes track a single pitch with much lower latency, which suits
+// vocals and bass lines.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DetectionMode {
+    #[name = "Polyphonic (FFT)"]
+    Polyphonic,
 
-    // Output for notes identified by the algorithm
-    #[persist = "notes-output"]
-    notes_output: Arc<Mutex<String>>,
+    #[name = "Monophonic (YIN)"]
+    Yin,
 
-    // Debug tracking
-    debug_messages: Arc<Mutex<String>>,
+    #[name = "Monophonic (MPM)"]
+    Mpm,
 }
 
-impl Default for FourierChords {
-    fn default() -> Self {
-        // Initialize an FFT Planner
-        let mut planner = FftPlanner::new();
+// Onset detection functions that can drive the onset detector
+#[derive(Enum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnsetMethod {
+    // Sum of magnitude increases across all bins. A good default for most material.
+    #[default]
+    #[name = "Spectral Flux"]
+    SpectralFlux,
+
+    // Distance between each bin and its prediction from the previous two frames, which also
+    // catches soft, pitched onsets that change phase more than magnitude
+    #[name = "Complex Domain"]
+    ComplexDomain,
+
+    // Increase in frequency-weighted energy. Favours percussive, bright attacks.
+    #[name = "High Frequency Content"]
+    HighFrequencyContent,
+}
 
-        // Use planner to create FFT algorithm
-        // Update value here if changing window size. Testing showed 65,536 to be a good balance
-        // between performance and algorithm accuracy. Also need to change window_size if changing
-        // this value.
-        let fft_algorithm = planner.plan_fft_forward(65536);
+// Inputs the analysis can listen to. The main input always passes through to the output
+// untouched, so the plugin can sit on one track and read the harmony of another that is routed to
+// its sidechain.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalysisSource {
+    #[name = "Main"]
+    Main,
 
-        Self {
-            params: Arc::new(FourierChordsParams::default()),
+    #[name = "Sidechain"]
+    Sidechain,
 
-            // Initialize sample rate to standard of 44.1khz. Will be updated in initialize function.
-            sample_rate: 44100.0,
+    // Main and sidechain summed
+    #[name = "Main + Sidechain"]
+    Both,
+}
+
+// How the channels of an input are combined for analysis
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    #[name = "Single Channel"]
+    Single,
+
+    #[name = "All Channels"]
+    Mixdown,
+}
+
+// Ways of writing note names
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamingSystem {
+    // C, D, E, ...
+    #[name = "Letters"]
+    Letters,
+
+    // Do, Re, Mi, ... with Do always on C
+    #[name = "Solfège (fixed do)"]
+    Solfege,
+
+    // C, D, E, ... with H for B natural and B for B flat
+    #[name = "German (H/B)"]
+    German,
+
+    // Scale degrees of the selected key
+    #[name = "Nashville numbers"]
+    Nashville,
+}
+
+// How the black keys are spelled
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spelling {
+    #[name = "Sharps"]
+    Sharps,
+
+    #[name = "Flats"]
+    Flats,
 
-            // Select window size. Will need changed manually if desired. If changed, the fft_forward
-            // value must be changed too in the above fft_algorithm initialization.
-            window_size: 65536,
+    // As they appear in the selected key, e.g. Bb in F major and A# in B major
+    #[name = "Key signature"]
+    Key,
+}
+
+// Major keys, each standing for its relative minor as well
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    #[name = "C / Am"]
+    C,
+
+    #[name = "Db / Bbm"]
+    Db,
 
-            // Initialize note data hashmap
-            // TODO: This should likely be handled by initialization function.
-            note_data: get_note_data(),
+    #[name = "D / Bm"]
+    D,
 
-            // Initialize complex buffer
-            complex_buffer: Vec::new(),
+    #[name = "Eb / Cm"]
+    Eb,
 
-            // Initialize sample vector
-            // sample_vec: vec![0.0; 264600],
-            sample_vec: Vec::new(),
+    #[name = "E / C#m"]
+    E,
 
-            // Vector for windowed values
-            windowed_values: Vec::new(),
+    #[name = "F / Dm"]
+    F,
 
-            // Initialize fft_algorithm to the one initialized in default function
-            fft_algorithm,
+    #[name = "F# / D#m"]
+    FSharp,
 
-            // Initialize Spectrum Data object with zeroed values
-            spectrum_data: Vec::new(),
+    #[name = "Gb / Ebm"]
+    Gb,
 
-            // Initialize default frequency resolution. Will be calculated again when spectrum is created.
-            frequency_resolution: 0.16666667,
+    #[name = "G / Em"]
+    G,
 
-            // Initialize default nyquist limit. Will be calculated again when spectrum is created.
-            nyquist_limit: 132300,
+    #[name = "Ab / Fm"]
+    Ab,
 
-            // Detection vectors
-            local_maxima: Vec::new(),
-            prominent_peaks: Vec::new(),
-            detected_notes: Vec::new(),
+    #[name = "A / F#m"]
+    A,
+
+    #[name = "Bb / Gm"]
+    Bb,
+
+    #[name = "B / G#m"]
+    B,
+}
 
-            // Magnitude threshold
-            magnitude_threshold: 0.0,
+// Editor state that is not stored in the parameters
+struct EditorData {
+    // Presets offered in the preset menu. User presets are read from disk when the editor opens
+    // and after saving.
+    factory_presets: Vec<Preset>,
+    user_presets: Vec<Preset>,
 
-            // Max magnitude
-            max_magnitude: 0.0,
+    // Name entered for saving a preset, and the outcome of the last load or save
+    preset_name: String,
+    preset_status: String,
 
-            // Prominence threshold
-            prominence_threshold: 0.0,
+    // Recent spectrogram columns, collected while the editor is open
+    spectrogram: Spectrogram,
 
-            // General prominence value
-            prominence: 0.0,
+    // Frame shown instead of the latest one while the display is frozen
+    frozen_frame: Option<AnalysisFrame>,
 
-            // Default note display value
-            notes_string_for_display: "".to_string(),
+    // Whether the last recognized chord stays on screen while no chord is recognized, and that
+    // chord
+    hold_chord: bool,
+    held_chord: Option<Chord>,
 
-            // Utility index counter for overwriting sample_vec values
-            counter: 0,
+    // Chords recognized while the editor is open, oldest first and without repeats. Copied as the
+    // progression when the host has no transport to build a chord grid from.
+    recent_chords: Vec<Chord>,
 
-            // Defaults to false
-            buffer_displayed: false,
+    // How chords are written when copied to the clipboard
+    copy_format: CopyFormat,
+
+    // Lowest level and the categories shown in the diagnostics panel, and the outcome of the last
+    // attempt to write the log to a file
+    log_level: LogLevel,
+    log_categories: [bool; LogCategory::ALL.len()],
+    log_status: String,
+}
+
+// Clipboard formats for chords and progressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyFormat {
+    // Chord names as shown in the editor, e.g. "Am7" or "| G | Em | C | D |"
+    Text,
+
+    // ChordPro chords, e.g. "[Am7]", with progressions written as a grid section
+    ChordPro,
+}
+
+impl Default for FourierChords {
+    fn default() -> Self {
+        // Queue for handing samples to the analyzer. Recreated in initialize for the host's
+        // sample rate.
+        let (sample_producer, sample_consumer) = rtrb::RingBuffer::new(sample_queue_capacity(44100.0));
+
+        // Channel for handing analysis results to the editor
+        let (analysis_input, analysis_output) = TripleBuffer::new(&AnalysisFrame::default()).split();
+
+        // One log queue each for the audio thread and the background analysis
+        let mut log = LogCollector::default();
+        let logger = log.logger();
+        let analysis_logger = log.logger();
+
+        Self {
+            params: Arc::new(FourierChordsParams::default()),
+
+            // Initialize sample rate to standard of 44.1khz. Will be updated in initialize function.
+            sample_rate: 44100.0,
+
+            analyzer: Arc::new(Mutex::new(Analyzer::new(sample_consumer, analysis_input, analysis_logger))),
+            sample_producer,
+            samples_since_task: 0,
+            analysis_hop: analysis_hop(44100.0),
+            reset_pending: false,
+            clear_history_requested: Arc::new(AtomicBool::new(false)),
+            log: Arc::new(Mutex::new(log)),
+            logger,
+            last_buffer_size: 0,
+            sample_queue_full: false,
+            invalid_input: false,
+            analysis_output: Arc::new(Mutex::new(analysis_output)),
         }
     }
 }
@@ -169,13 +359,44 @@ impl Default for FourierChordsParams {
     fn default() -> Self {
         Self {
             // Default editor state           ]]
-            editor_state: EguiState::from_size(400, 300),
-
-            // Default note value
-            notes_output: Arc::new(Mutex::new("".to_string())),
-
-            // Default debug message
-            debug_messages: Arc::new(Mutex::new("".to_string())),
+            editor_state: EguiState::from_size(500, 780),
+
+            detection_mode: EnumParam::new("Detection Mode", DetectionMode::Polyphonic),
+            analysis_source: EnumParam::new("Analysis Source", AnalysisSource::Main),
+            channel_mode: EnumParam::new("Channel Mode", ChannelMode::Single),
+            analysis_channel: IntParam::new("Analysis Channel", 1, IntRange::Linear { min: 1, max: 8 }),
+
+            // Defaults to the range of a piano
+            min_frequency: FloatParam::new(
+                "Lowest Frequency",
+                26.0,
+                FloatRange::Skewed { min: 20.0, max: 2000.0, factor: FloatRange::skew_factor(-2.0) },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+
+            max_frequency: FloatParam::new(
+                "Highest Frequency",
+                4435.0,
+                FloatRange::Skewed { min: 100.0, max: 20000.0, factor: FloatRange::skew_factor(-2.0) },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+
+            note_threshold: FloatParam::new(
+                "Note Threshold",
+                -48.0,
+                FloatRange::Linear { min: -90.0, max: -12.0 },
+            )
+            .with_unit(" dBFS")
+            .with_step_size(0.5),
+
+            suppress_percussion: BoolParam::new("Suppress Percussion", false),
+            onset_method: EnumParam::new("Onset Method", OnsetMethod::SpectralFlux),
+
+            naming_system: EnumParam::new("Note Names", NamingSystem::Letters),
+            spelling: EnumParam::new("Spelling", Spelling::Sharps),
+            key: EnumParam::new("Key", Key::C),
         }
     }
 }
@@ -190,18 +411,60 @@ impl Plugin for FourierChords {
 
     // The first audio IO layout is used as the default. The other layouts may be selected either
     // explicitly or automatically by the host or the user depending on the plugin API/backend.
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
-
-        aux_input_ports: &[],
-        aux_output_ports: &[],
-
-        // Individual ports and the layout as a whole can be named here. By default these names
-        // are generated as needed. This layout will be called 'Stereo', while a layout with
-        // only one input and output channel would be called 'Mono'.
-        names: PortNames::const_default(),
-    }];
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        // Stereo with a stereo sidechain that can be analysed instead of or along with the main
+        // input. Comes first so that hosts which only use the default layout offer the sidechain.
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+
+            aux_input_ports: &[new_nonzero_u32(2)],
+            aux_output_ports: &[],
+
+            names: PortNames {
+                layout: Some("Stereo with Sidechain"),
+                main_input: None,
+                main_output: None,
+                aux_inputs: &["Sidechain"],
+                aux_outputs: &[],
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+
+            aux_input_ports: &[],
+            aux_output_ports: &[],
+
+            // Individual ports and the layout as a whole can be named here. By default these names
+            // are generated as needed. This layout will be called 'Stereo', while a layout with
+            // only one input and output channel would be called 'Mono'.
+            names: PortNames::const_default(),
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            ..AudioIOLayout::const_default()
+        },
+        // There is no analyzer-only layout without outputs: the main input reaches the plugin
+        // through the main output buffer, so it cannot be read without main outputs.
+        // Multichannel buses, e.g. quad, 5.1 and 7.1
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(4),
+            main_output_channels: NonZeroU32::new(4),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(6),
+            main_output_channels: NonZeroU32::new(6),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(8),
+            main_output_channels: NonZeroU32::new(8),
+            ..AudioIOLayout::const_default()
+        },
+    ];
 
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
@@ -213,26 +476,255 @@ impl Plugin for FourierChords {
     // messages here. The type implements the `SysExMessage` trait, which allows conversion to and
     // from plain byte buffers.
     type SysExMessage = ();
-    // More advanced plugins can use this to run expensive background tasks. See the field's
-    // documentation for more information. `()` means that the plugin does not have any background
-    // tasks.
-    type BackgroundTask = ();
+    // The FFT analysis runs as a background task so that it never stalls the audio thread
+    type BackgroundTask = AnalysisTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let analyzer = self.analyzer.clone();
+
+        let log = self.log.clone();
+        let poison_reported = AtomicBool::new(false);
+
+        Box::new(move |task| {
+            // A panic in an earlier task leaves the analyzer in an unknown state, so analysis stays
+            // off from then on. This is reported once rather than for every task.
+            let (result, newly_poisoned) = match analyzer.lock() {
+                Ok(mut analyzer) => (analyzer.run(task), false),
+                Err(_) => (Ok(()), !poison_reported.swap(true, Ordering::Relaxed)),
+            };
+
+            // Move the log records to the history and the log file here, off the audio thread. If
+            // the editor is reading the log right now, they stay queued until the next task. A
+            // failed task waits for the log so that the error is never lost.
+            let collector = if result.is_err() || newly_poisoned { log.lock().ok() } else { log.try_lock().ok() };
+            if let Some(mut log) = collector {
+                if newly_poisoned {
+                    log.record(LogLevel::Error, LogCategory::Input, format_args!(
+                        "Analysis disabled: an earlier analysis task panicked"));
+                }
+                if let Err(error) = result {
+                    log.record(LogLevel::Error, LogCategory::Input, format_args!("Analysis task failed: {}", error));
+                }
+                log.collect();
+            }
+        })
+    }
+
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        let params = self.params.clone();
         let editor_state = self.params.editor_state.clone();
-        let notes_output = self.params.notes_output.clone();
-        let debug_messages = self.params.debug_messages.clone();
+        let analysis_output = self.analysis_output.clone();
+        let clear_history_requested = self.clear_history_requested.clone();
+        let log = self.log.clone();
 
         create_egui_editor(
             editor_state,
-            (),
-            |_, _| {},
-            move |egui_ctx, setter, _state| {
+            EditorData {
+                factory_presets: factory_presets(),
+                user_presets: Vec::new(),
+                preset_name: String::new(),
+                preset_status: String::new(),
+                spectrogram: Spectrogram::default(),
+                frozen_frame: None,
+                hold_chord: false,
+                held_chord: None,
+                recent_chords: Vec::with_capacity(MAX_RECENT_CHORDS),
+                copy_format: CopyFormat::Text,
+                log_level: LogLevel::Info,
+                log_categories: [true; LogCategory::ALL.len()],
+                log_status: String::new(),
+            },
+            |_, data| {
+                data.user_presets = load_user_presets();
+
+                // Each opened window has its own egui context, so the texture has to be created again
+                data.spectrogram = Spectrogram::default();
+            },
+            move |egui_ctx, setter, data| {
+                // Fetch the most recently published analysis frame
+                let mut analysis_output = match analysis_output.lock() {
+                    Ok(analysis_output) => analysis_output,
+                    Err(_) => return,
+                };
+                let latest_frame = analysis_output.read();
+
+                // While frozen, everything keeps showing the frame from the moment of freezing
+                if data.frozen_frame.is_none() {
+                    data.spectrogram.update(latest_frame);
+
+                    if let Some(Some(chord)) = latest_frame.chord {
+                        data.held_chord = Some(chord);
+                        if data.recent_chords.last() != Some(&chord) {
+                            if data.recent_chords.len() == MAX_RECENT_CHORDS {
+                                data.recent_chords.remove(0);
+                            }
+                            data.recent_chords.push(chord);
+                        }
+                    }
+                }
+                let frame = data.frozen_frame.clone().unwrap_or_else(|| latest_frame.clone());
+
+                // Keyboard shortcuts, unless a text field is being typed in
+                let pressed = |key| !egui_ctx.wants_keyboard_input() && egui_ctx.input(|input| input.key_pressed(key));
+
+                let naming = NoteNaming {
+                    system: params.naming_system.value(),
+                    spelling: params.spelling.value(),
+                    key: params.key.value(),
+                };
+
                 egui::CentralPanel::default().show(egui_ctx, |ui| {
+                    // Preset selector, and saving the current detection settings as a user preset
+                    ui.horizontal(|ui| {
+                        ui.label("Preset");
+
+                        let mut selected = None;
+                        egui::ComboBox::from_id_source("preset")
+                            .selected_text("Load")
+                            .show_ui(ui, |ui| {
+                                for preset in &data.factory_presets {
+                                    if ui.selectable_label(false, preset.name.as_str()).clicked() {
+                                        selected = Some(preset.clone());
+                                    }
+                                }
+                                if !data.user_presets.is_empty() {
+                                    ui.separator();
+                                }
+                                for preset in &data.user_presets {
+                                    if ui.selectable_label(false, preset.name.as_str()).clicked() {
+                                        selected = Some(preset.clone());
+                                    }
+                                }
+                            });
+                        if let Some(preset) = selected {
+                            apply_preset(&params, setter, &preset);
+                            data.preset_status = format!("Loaded {}", preset.name);
+                            data.preset_name = preset.name;
+                        }
+
+                        ui.add(egui::TextEdit::singleline(&mut data.preset_name).desired_width(120.0));
+                        let has_name = !data.preset_name.trim().is_empty();
+                        if ui.add_enabled(has_name, egui::Button::new("Save")).clicked() {
+                            data.preset_status = match save_user_preset(&current_preset(&params, &data.preset_name)) {
+                                Ok(path) => format!("Saved to {}", path.display()),
+                                Err(error) => format!("Could not save preset: {}", error),
+                            };
+                            data.user_presets = load_user_presets();
+                        }
+                    });
+                    if !data.preset_status.is_empty() {
+                        ui.label(egui::RichText::new(&data.preset_status).small());
+                    }
+
+                    // Detection mode and analysis source selectors
+                    ui.horizontal(|ui| {
+                        ui.label("Mode");
+                        ui.add(widgets::ParamSlider::for_param(&params.detection_mode, setter));
+                        ui.label("Source");
+                        ui.add(widgets::ParamSlider::for_param(&params.analysis_source, setter));
+                    });
+
+                    // Channel selection for multichannel inputs
+                    ui.horizontal(|ui| {
+                        ui.label("Channels");
+                        ui.add(widgets::ParamSlider::for_param(&params.channel_mode, setter));
+                        ui.add(widgets::ParamSlider::for_param(&params.analysis_channel, setter));
+                    });
+
+                    // Frequency range, shown with the nearest notes
+                    ui.horizontal(|ui| {
+                        ui.label("Range");
+                        ui.add(widgets::ParamSlider::for_param(&params.min_frequency, setter));
+                        ui.add(widgets::ParamSlider::for_param(&params.max_frequency, setter));
+
+                        let note_name = |frequency: f32| match frequency_to_note(frequency) {
+                            Some(midi_note) => naming.note_name(midi_note),
+                            None => "-".to_string(),
+                        };
+                        ui.label(format!("{} to {}",
+                                         note_name(params.min_frequency.value()),
+                                         note_name(params.max_frequency.value())));
+                    });
+
+                    // Level notes have to reach
+                    ui.horizontal(|ui| {
+                        ui.label("Threshold");
+                        ui.add(widgets::ParamSlider::for_param(&params.note_threshold, setter));
+                    });
+
+                    // Percussion filter for full mixes and drum loops, and how attacks are found
+                    ui.horizontal(|ui| {
+                        ui.label("Drums");
+                        ui.add(widgets::ParamSlider::for_param(&params.suppress_percussion, setter));
+                        ui.label("Onsets");
+                        ui.add(widgets::ParamSlider::for_param(&params.onset_method, setter));
+                    });
+
+                    // Note naming system, spelling of accidentals and the key they refer to
+                    ui.horizontal(|ui| {
+                        ui.label("Names");
+                        ui.add(widgets::ParamSlider::for_param(&params.naming_system, setter));
+                        ui.add(widgets::ParamSlider::for_param(&params.spelling, setter));
+                        ui.add(widgets::ParamSlider::for_param(&params.key, setter));
+                    });
+
+                    // The chord on screen. While holding, the last recognized chord stays when no
+                    // chord is recognized.
+                    let held_chord = data.held_chord.filter(|_| data.hold_chord && !matches!(frame.chord, Some(Some(_))));
+                    let shown_chord = match frame.chord {
+                        Some(Some(chord)) => Some(chord),
+                        _ => held_chord,
+                    };
+
+                    // Display controls, and copying the chord or the progression to the clipboard
+                    ui.horizontal(|ui| {
+                        let freeze = ui.selectable_label(data.frozen_frame.is_some(), "Freeze")
+                            .on_hover_text("Stop updating the display (F)");
+                        if freeze.clicked() || pressed(egui::Key::F) {
+                            data.frozen_frame = match data.frozen_frame {
+                                Some(_) => None,
+                                None => Some(frame.clone()),
+                            };
+                        }
+
+                        let hold = ui.selectable_label(data.hold_chord, "Hold")
+                            .on_hover_text("Keep showing the last chord while no chord is recognized (H)");
+                        if hold.clicked() || pressed(egui::Key::H) {
+                            data.hold_chord = !data.hold_chord;
+                        }
+
+                        let clear = ui.button("Clear")
+                            .on_hover_text("Clear the chord history, the progression and the spectrogram (X)");
+                        if clear.clicked() || pressed(egui::Key::X) {
+                            clear_history_requested.store(true, Ordering::Relaxed);
+                            data.spectrogram.clear();
+                            data.held_chord = None;
+                            data.recent_chords.clear();
+                        }
+
+                        ui.separator();
+                        ui.label("Copy");
+                        ui.selectable_value(&mut data.copy_format, CopyFormat::Text, "Text");
+                        ui.selectable_value(&mut data.copy_format, CopyFormat::ChordPro, "ChordPro");
+
+                        let mut copied_text = None;
+                        let copy_chord = ui.button("Chord").on_hover_text("Copy the current chord (C)");
+                        if copy_chord.clicked() || pressed(egui::Key::C) {
+                            copied_text = shown_chord.map(|chord| chord_clipboard_text(&chord, &naming, data.copy_format));
+                        }
+                        let copy_progression = ui.button("Progression").on_hover_text("Copy the chord progression (P)");
+                        if copy_progression.clicked() || pressed(egui::Key::P) {
+                            copied_text = Some(progression_clipboard_text(&frame.progression, &data.recent_chords, &naming, data.copy_format));
+                        }
+                        if let Some(text) = copied_text.filter(|text| !text.is_empty()) {
+                            ui.ctx().output_mut(|output| output.copied_text = text);
+                        }
+                    });
+
                     // Display a static label for "Identified Notes"
                     ui.vertical_centered(|ui| {
                         // Display "Identified Notes" with custom style
@@ -243,29 +735,147 @@ impl Plugin for FourierChords {
                         );
                     });
 
-                    // Display the dynamically loaded notes
-                    egui::ScrollArea::vertical().show(ui, |ui| {
-                        if let Ok(notes_output) = notes_output.lock() {
-                            // Center the dynamically loaded notes
-                            ui.vertical_centered(|ui| {
-                                ui.label(
-                                    egui::RichText::new(format!("{}", *notes_output))
-                                        .size(16.0)
-                                );
-                            });
-                        }
+                    // Display the chord recognized by the chord tracker
+                    let chord_text = match (frame.chord, held_chord) {
+                        (_, Some(chord)) => format!("{} (held)", naming.chord_name(&chord)),
+                        (Some(Some(chord)), None) => naming.chord_name(&chord),
+                        (Some(None), None) => "None".to_string(),
+                        (None, None) => "".to_string(),
+                    };
+                    ui.vertical_centered(|ui| {
+                        ui.label(
+                            egui::RichText::new(format!("Chord: {}", chord_text))
+                                .size(22.0)
+                        );
                     });
 
-                    // Flexible space to push the debug area to the bottom
-                    ui.add_space(ui.available_height() / 1.15);
+                    // Display the chord progression aligned to the host's bars
+                    let progression = frame.progression.progression(|chord| naming.chord_name(chord));
+                    if !progression.is_empty() {
+                        ui.vertical_centered(|ui| {
+                            ui.label(
+                                egui::RichText::new(progression)
+                                    .monospace()
+                                    .size(16.0)
+                            );
+                        });
+                    }
+
+                    // Display the notes that are currently sounding with their tuning deviation
+                    let notes_text = if frame.notes().is_empty() {
+                        "None".to_string()
+                    } else {
+                        frame.notes()
+                            .iter()
+                            .map(|note| format!("{} ({:+.0}¢)", naming.note_name(note.midi as usize), note.cents))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    };
+                    ui.vertical_centered(|ui| {
+                        ui.label(
+                            egui::RichText::new(notes_text)
+                                .size(16.0)
+                        );
+                    });
 
-                    // Scrollable debugging area, located beneath the notes display
-                    egui::ScrollArea::vertical().show(ui, |ui| {
-                        if let Ok(debug_messages) = debug_messages.lock() {
-                            ui.vertical_centered(|ui| {
-                                ui.label(&*debug_messages);
-                            });
+                    // Display the spectrum of the most recent analysis frame
+                    ui.add_space(8.0);
+                    draw_spectrum(ui, &frame.spectrum, frame.min_frequency, frame.max_frequency);
+
+                    // Display the recent spectra as a spectrogram, with the notes that were detected
+                    ui.add_space(8.0);
+                    draw_spectrogram(ui, &mut data.spectrogram, &naming);
+
+                    // Input level meters
+                    ui.label(format!("Peak: {:.1} dBFS    RMS: {:.1} dBFS", frame.peak_level_db, frame.rms_level_db));
+
+                    // Diagnostics panel with the host configuration and the log, located beneath the
+                    // notes display
+                    ui.separator();
+                    egui::CollapsingHeader::new("Diagnostics").show(ui, |ui| {
+                        ui.label(format!("Sample Rate: {:?}    Buffer Size: {:?}", frame.sample_rate, frame.buffer_size));
+
+                        // Time spent on the most recent FFT analysis frame, by stage
+                        let timings = &frame.frame_timings;
+                        if timings.frames > 0 {
+                            ui.label(format!(
+                                "FFT Frame: {:.2} ms (window {:.2}, FFT {:.2}, magnitudes {:.2}, chroma and chords {:.2})",
+                                timings.total_ms, timings.window_ms, timings.fft_ms, timings.magnitude_ms, timings.chroma_ms));
+                            ui.label(format!("Average: {:.2} ms    Worst: {:.2} ms    Frames: {}",
+                                             timings.average_total_ms, timings.max_total_ms, timings.frames));
+                        } else {
+                            ui.label("FFT Frame: no frames analysed yet");
+                        }
+
+                        // Time spent in the current pitch estimator, which runs on its own hop
+                        if timings.estimator_frames > 0 {
+                            ui.label(format!(
+                                "Pitch Estimator: {:.2} ms    Average: {:.2} ms    Worst: {:.2} ms    Frames: {}",
+                                timings.estimator_ms, timings.average_estimator_ms, timings.max_estimator_ms,
+                                timings.estimator_frames));
+                        } else {
+                            ui.label("Pitch Estimator: no frames analysed yet");
                         }
+
+                        let mut log = match log.lock() {
+                            Ok(log) => log,
+                            Err(_) => return,
+                        };
+                        log.collect();
+
+                        // Filters
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source("log-level")
+                                .selected_text(data.log_level.name())
+                                .show_ui(ui, |ui| {
+                                    for level in LogLevel::ALL {
+                                        ui.selectable_value(&mut data.log_level, level, level.name());
+                                    }
+                                });
+                            for (shown, category) in data.log_categories.iter_mut().zip(LogCategory::ALL) {
+                                ui.checkbox(shown, category.name());
+                            }
+                        });
+
+                        // Clearing, and writing the log to a file
+                        ui.horizontal(|ui| {
+                            if ui.button("Clear Log").clicked() {
+                                log.clear();
+                            }
+
+                            let mut writing_file = log.is_writing_file();
+                            if ui.checkbox(&mut writing_file, "Write to file").changed() {
+                                data.log_status = if writing_file {
+                                    match log.start_file() {
+                                        Ok(path) => format!("Writing to {}", path.display()),
+                                        Err(error) => format!("Could not open log file: {}", error),
+                                    }
+                                } else {
+                                    log.stop_file();
+                                    String::new()
+                                };
+                            }
+                        });
+                        if !data.log_status.is_empty() {
+                            ui.label(egui::RichText::new(&data.log_status).small());
+                        }
+
+                        egui::ScrollArea::vertical()
+                            .max_height(160.0)
+                            .stick_to_bottom(true)
+                            .show(ui, |ui| {
+                                let shown = log
+                                    .history()
+                                    .filter(|record| record.level >= data.log_level && data.log_categories[record.category as usize]);
+                                for record in shown {
+                                    ui.label(
+                                        egui::RichText::new(record.to_string())
+                                            .monospace()
+                                            .small()
+                                            .color(log_level_colour(record.level))
+                                    );
+                                }
+                            });
                     });
                 });
             },
@@ -285,88 +895,158 @@ impl Plugin for FourierChords {
         // Set sample rate
         self.sample_rate = _buffer_config.sample_rate;
 
-        // Set all FFT buffers to be equal to desired window size
-        self.complex_buffer.resize(self.window_size, Complex { re: 0.0, im: 0.0 });
-
-        self.windowed_values.resize(self.window_size, 0.0);
-
-        self.spectrum_data.resize(self.window_size, SpectrumData { frequency: 0.0, magnitude: 0.0, index: 0 });
+        // Rebuild the sample queue and the analysis for the new configuration. Anything queued
+        // or measured at the old sample rate is dropped with them.
+        let (sample_producer, sample_consumer) = rtrb::RingBuffer::new(sample_queue_capacity(self.sample_rate));
+        self.sample_producer = sample_producer;
+        if let Ok(mut analyzer) = self.analyzer.lock() {
+            if let Err(error) = analyzer.initialize(self.sample_rate, sample_consumer) {
+                self.logger.log(LogLevel::Error, LogCategory::Host, format_args!("Cannot initialize: {}", error));
+                return false;
+            }
+        }
+        self.samples_since_task = 0;
+        self.analysis_hop = analysis_hop(self.sample_rate);
+        self.reset_pending = false;
+        self.sample_queue_full = false;
+        self.invalid_input = false;
+
+        let channels = |channels: Option<NonZeroU32>| channels.map_or(0, NonZeroU32::get);
+        self.logger.log(LogLevel::Info, LogCategory::Host, format_args!(
+            "Initialized at {} Hz with up to {} samples per buffer, {} main and {} sidechain input channels",
+            self.sample_rate,
+            _buffer_config.max_buffer_size,
+            channels(_audio_io_layout.main_input_channels),
+            _audio_io_layout.aux_input_ports.first().map_or(0, |&port| port.get())));
 
         true
     }
 
     fn reset(&mut self) {
-        // Reset buffers and envelopes here. This can be called from the audio thread and may not
-        // allocate. You can remove this function if you do not need it.
+        // Called on transport stop and start. The analysis state lives on the background thread,
+        // so clearing it is left to a task sent from the next process call.
+        self.samples_since_task = 0;
+        self.reset_pending = true;
+        self.logger.log(LogLevel::Info, LogCategory::Host, format_args!("Analysis reset by the host"));
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Tasks run in order, so the state is cleared before the samples below are analysed
+        if self.reset_pending {
+            self.reset_pending = false;
+            context.execute_background(AnalysisTask::Reset);
+        }
+        if self.clear_history_requested.swap(false, Ordering::Relaxed) {
+            context.execute_background(AnalysisTask::ClearHistory);
+        }
 
-        // Print buffer size to debug window
-        if !self.buffer_displayed {
-            {
-                let message = format!("Sample Rate: {:?}\nBuffer Size: {:?}", self.sample_rate, buffer.samples());
-                let mut debug_messages = self.params.debug_messages.lock().unwrap();
-                debug_messages.push_str(&message);
+        // The analysed signal is the main input, the sidechain, or both summed. Without a
+        // sidechain the main input is analysed whatever the source says.
+        let main_bus = buffer.as_slice_immutable();
+        let sidechain_bus = aux.inputs
+            .first()
+            .map(|input| input.as_slice_immutable())
+            .filter(|channels| !channels.is_empty());
+        let (first_bus, second_bus) = match (self.params.analysis_source.value(), sidechain_bus) {
+            (AnalysisSource::Main, _) | (_, None) => (main_bus, None),
+            (AnalysisSource::Sidechain, Some(sidechain_bus)) => (sidechain_bus, None),
+            (AnalysisSource::Both, Some(sidechain_bus)) => (main_bus, Some(sidechain_bus)),
+        };
+
+        // Each bus is reduced to one channel, or mixed down to one. NaN and infinite samples are
+        // analysed as silence.
+        let channel_mode = self.params.channel_mode.value();
+        let channel = self.params.analysis_channel.value() as usize - 1;
+        let input_sample = |i: usize| {
+            bus_sample(first_bus, channel_mode, channel, i)
+                + second_bus.map_or(0.0, |bus| bus_sample(bus, channel_mode, channel, i))
+        };
+        let analysed_sample = |i: usize| sanitize_sample(input_sample(i));
+        let num_samples = first_bus.first().map_or(0, |channel| channel.len());
+
+        // Input levels of the analysed signal
+        let (peak_level_db, rms_level_db) = if num_samples > 0 {
+            block_levels((0..num_samples).map(&analysed_sample))
+        } else {
+            (FLOOR_DB, FLOOR_DB)
+        };
+
+        // Queue the analysed signal. The audio itself passes through untouched. If the analyzer
+        // falls so far behind that the queue is full, the newest samples are dropped.
+        let mut samples_dropped = 0;
+        let mut invalid_samples = 0;
+        for i in 0..num_samples {
+            let sample = input_sample(i);
+            if !sample.is_finite() {
+                invalid_samples += 1;
+            }
+            if self.sample_producer.push(sanitize_sample(sample)).is_err() {
+                samples_dropped += 1;
             }
-
-            self.buffer_displayed = true;
         }
-
-        for mut sample_frame in buffer.iter_samples() {
-            if let Some(&mut left_sample) = sample_frame.get_mut(0) {
-                // Avoids adding noise
-                if left_sample.abs() > 0.001 {
-                    self.sample_vec.push(left_sample);
-                }
-
-                // Check if we've reached the desired sample count
-                if self.sample_vec.len() >= self.window_size {
-                    // Perform analysis (e.g., FFT)
-                    perform_analysis(self);
-
-                    // Clear the sample_vec or handle overlap
-                    self.sample_vec.clear(); // or handle overlap as needed
-                    break; // Important to break to avoid overfilling the buffer
-                }
+        self.samples_since_task += num_samples;
+
+        if (invalid_samples > 0) != self.invalid_input {
+            self.invalid_input = invalid_samples > 0;
+            if self.invalid_input {
+                self.logger.log(LogLevel::Warning, LogCategory::Input, format_args!(
+                    "{} of {} input samples are NaN or infinite and are analysed as silence",
+                    invalid_samples, num_samples));
+            } else {
+                self.logger.log(LogLevel::Info, LogCategory::Input, format_args!("Input samples are valid again"));
             }
         }
 
+        if (samples_dropped > 0) != self.sample_queue_full {
+            self.sample_queue_full = samples_dropped > 0;
+            if self.sample_queue_full {
+                self.logger.log(LogLevel::Warning, LogCategory::Buffers, format_args!(
+                    "Sample queue is full, {} of {} samples dropped", samples_dropped, num_samples));
+            } else {
+                self.logger.log(LogLevel::Info, LogCategory::Buffers, format_args!("Sample queue has room again"));
+            }
+        }
 
-        // Update GUI with newly detected notes
-        if self.params.editor_state.is_open() && !self.detected_notes.is_empty() {
-            // Build a string from the detected notes
-            let new_notes_string = self.detected_notes.join(", ");
+        if buffer.samples() != self.last_buffer_size {
+            self.last_buffer_size = buffer.samples();
+            self.logger.log(LogLevel::Info, LogCategory::Buffers, format_args!(
+                "Host buffer size changed to {} samples", self.last_buffer_size));
+        }
 
-            // Update only if there are new notes to display
-            if !self.notes_string_for_display.contains(&new_notes_string) {
-                let updated_notes = if new_notes_string.is_empty() {
-                    "None".to_string()
-                } else {
-                    new_notes_string
-                };
+        if self.samples_since_task >= self.analysis_hop {
+            self.samples_since_task = 0;
 
-                // Update the shared notes_output variable
-                if let Ok(mut notes_output) = self.params.notes_output.lock() {
-                    *notes_output = updated_notes;
+            // Transport position at the end of this buffer, used to quantise chords to the host's
+            // beat grid. The reported position refers to the start of the buffer.
+            let transport = context.transport();
+            let position_beats = match (transport.playing, transport.tempo, transport.pos_beats()) {
+                (true, Some(tempo), Some(position_beats)) => {
+                    Some(position_beats + buffer.samples() as f64 / self.sample_rate as f64 * tempo / 60.0)
                 }
-
-                // Now that we've updated the GUI, clear the detected_notes
-                // self.detected_notes.clear();
-            }
+                _ => None,
+            };
+
+            context.execute_background(AnalysisTask::Analyze(BlockInfo {
+                position_beats,
+                tempo: transport.tempo,
+                beats_per_bar: transport.time_sig_numerator.unwrap_or(4).max(1) as usize,
+                peak_level_db,
+                rms_level_db,
+                buffer_size: buffer.samples(),
+                mode: self.params.detection_mode.value(),
+                min_frequency: self.params.min_frequency.value(),
+                max_frequency: self.params.max_frequency.value(),
+                note_threshold_db: self.params.note_threshold.value(),
+                suppress_percussion: self.params.suppress_percussion.value(),
+                onset_method: self.params.onset_method.value(),
+            }));
         }
 
-        self.local_maxima.clear();
-        self.prominent_peaks.clear();
-
-        // This breaks the VST
-        // self.spectrum_data.clear();
-
         ProcessStatus::Normal
     }
 }
@@ -377,190 +1057,116 @@ impl ClapPlugin for FourierChords {
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
 
-    // Don't forget to change these features
-    const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::AudioEffect, ClapFeature::Stereo];
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Analyzer,
+        ClapFeature::Utility,
+        ClapFeature::Mono,
+        ClapFeature::Stereo,
+        ClapFeature::Surround,
+    ];
 }
 
 impl Vst3Plugin for FourierChords {
     const VST3_CLASS_ID: [u8; 16] = *b"FourierChordsNJM";
 
-    // And also don't forget to change these categories
     const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
-        &[Vst3SubCategory::Fx, Vst3SubCategory::Dynamics];
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Analyzer, Vst3SubCategory::Tools];
 }
 
 nih_export_clap!(FourierChords);
 nih_export_vst3!(FourierChords);
 
 
-// Spectrum Data Structure Definition
-#[derive(Debug, Clone)]
-struct SpectrumData {
-    frequency: f32,
-    magnitude: f32,
-    index: usize,
+// Changes a parameter from the editor as a single host gesture
+fn set_parameter<P: Param>(setter: &ParamSetter, param: &P, value: P::Plain) {
+    setter.begin_set_parameter(param);
+    setter.set_parameter(param, value);
+    setter.end_set_parameter(param);
 }
 
-// Executes algorithm
-fn perform_analysis(fourier_chords: &mut FourierChords) {
-    // Apply the window function to the audio data (Hanning, etc.)
-    apply_window_function(fourier_chords);
-
-    // Perform the FFT
-    perform_fft(fourier_chords);
-
-    // Get the spectrum data
-    get_spectrum_data(fourier_chords);
-
-
-    // Identify notes
-    identify_notes(fourier_chords);
+// Sets every parameter a preset covers
+fn apply_preset(params: &FourierChordsParams, setter: &ParamSetter, preset: &Preset) {
+    set_parameter(setter, &params.detection_mode, preset.detection_mode);
+    set_parameter(setter, &params.min_frequency, preset.min_frequency);
+    set_parameter(setter, &params.max_frequency, preset.max_frequency);
+    set_parameter(setter, &params.note_threshold, preset.note_threshold_db);
+    set_parameter(setter, &params.suppress_percussion, preset.suppress_percussion);
+    set_parameter(setter, &params.onset_method, preset.onset_method);
 }
 
-// Function Definitions
-fn perform_fft(fourier_chords: &mut FourierChords) -> () {
-    // Check that the input and complex_vec are the same length
-    assert_eq!(fourier_chords.windowed_values.len(),
-               fourier_chords.complex_buffer.len(),
-               "Input and complex_vec must be of the same length");
-
-    // Populate the complex_vec with values from windowed vec
-    for (complex, &input_val) in fourier_chords.complex_buffer
-        .iter_mut()
-        .zip(fourier_chords.windowed_values.iter()) {
-        *complex = Complex { re: input_val, im: 0.0 };
+// The current detection settings as a preset
+fn current_preset(params: &FourierChordsParams, name: &str) -> Preset {
+    Preset {
+        name: name.trim().to_string(),
+        detection_mode: params.detection_mode.value(),
+        min_frequency: params.min_frequency.value(),
+        max_frequency: params.max_frequency.value(),
+        note_threshold_db: params.note_threshold.value(),
+        suppress_percussion: params.suppress_percussion.value(),
+        onset_method: params.onset_method.value(),
     }
-
-    // Perform forward FFT on input data
-    fourier_chords.fft_algorithm.process(&mut fourier_chords.complex_buffer);
 }
 
-
-// Utility function to apply the window function in place
-fn apply_window_function(fourier_chords: &mut FourierChords) {
-    for (i, (&sample, windowed_value)) in fourier_chords.sample_vec.iter().zip(fourier_chords.windowed_values.iter_mut()).enumerate() {
-        let window_value = 0.5 - 0.5 * (2.0 * PI * i as f32 / (fourier_chords.window_size as f32 - 1.0)).cos();
-        *windowed_value = sample * window_value;
+// A chord as copied to the clipboard
+fn chord_clipboard_text(chord: &Chord, naming: &NoteNaming, format: CopyFormat) -> String {
+    match format {
+        CopyFormat::Text => naming.chord_name(chord),
+        CopyFormat::ChordPro => format!("[{}]", naming.chord_name(chord)),
     }
 }
 
-// Transforms buffer of complex numbers from FFT forward transform and returns vector of
-// SpectrumData, which contains fields for frequencies and magnitudes
-fn get_spectrum_data(fourier_chords: &mut FourierChords) -> () {
-    fourier_chords.nyquist_limit = fourier_chords.sample_vec.len() / 2;
-    fourier_chords.frequency_resolution = fourier_chords.sample_rate / fourier_chords.sample_vec.len() as f32;
-
-    for (i, spectrum_data) in fourier_chords.spectrum_data
-        .iter_mut()
-        .enumerate()
-        .take(fourier_chords.nyquist_limit) {
-
-        let frequency = i as f32 * fourier_chords.frequency_resolution;
-        let magnitude = fourier_chords.complex_buffer[i].norm();
-
-        spectrum_data.frequency = frequency;
-        spectrum_data.magnitude = magnitude;
-        spectrum_data.index = i;
+// The progression as copied to the clipboard. Uses the bars of the host's beat grid if there are
+// any, and otherwise the chords recognized while the editor was open.
+fn progression_clipboard_text(grid: &ChordGrid, recent_chords: &[Chord], naming: &NoteNaming, format: CopyFormat) -> String {
+    let grid_text = match format {
+        CopyFormat::Text => grid.progression(|chord| naming.chord_name(chord)),
+        CopyFormat::ChordPro => grid.chordpro_grid(|chord| naming.chord_name(chord)),
+    };
+    if !grid_text.is_empty() {
+        return grid_text;
     }
-}
-
-// Function to identify notes in the spectrum
-fn identify_notes(fourier_chords: &mut FourierChords) -> () {
-    // Calculate local maxima of given spectrum data
-    get_local_maxima(fourier_chords);
 
-    // TODO: Finish peak picking algorithm process
-    get_prominent_peaks(fourier_chords);
-
-    // Initializes frequency to note hash map
-    let freq_to_note = get_note_data();
-
-    for value in &fourier_chords.prominent_peaks {
-        let closest_frequency = freq_to_note
-            .keys()
-            .min_by(|&freq1, &freq2| {
-                (freq1 - value.frequency)
-                    .abs()
-                    .partial_cmp(&(freq2 - value.frequency).abs())
-                    .unwrap()
-            })
-            .unwrap();
-
-        let note = freq_to_note.get(closest_frequency).unwrap(); // Get the note corresponding to the closest matching frequency
-
-        if !fourier_chords.detected_notes.contains(&note.to_string()) {
-            fourier_chords.detected_notes.push(note.clone()); // Clone the note string and add it to matched_notes
-        }
-    }
+    let separator = match format {
+        CopyFormat::Text => " - ",
+        CopyFormat::ChordPro => " ",
+    };
+    recent_chords
+        .iter()
+        .map(|chord| chord_clipboard_text(chord, naming, format))
+        .collect::<Vec<String>>()
+        .join(separator)
 }
 
-// TODO: Consider adding these as FFT Chord implementation functions; seems like that would be more
-//  straightforward based on how many functions we're defining that depend on traits
-// Calculate local maxima
-fn get_local_maxima(fourier_chords: &mut FourierChords) -> () {
-    // Calculate magnitude threshold and assign it. Play with this value to optimize execution time.
-    // Currently, we're using a threshold of one third of the maximum magnitude.
-    fourier_chords.magnitude_threshold = max_magnitude(&fourier_chords.spectrum_data) / 3.0;
-
-    // Identifies local maxima and pushes them to maxima vector
-    for i in 1..fourier_chords.spectrum_data.len() - 1 {
-        if fourier_chords.spectrum_data[i].magnitude < fourier_chords.magnitude_threshold {
-            continue;
-        }
-        if fourier_chords.spectrum_data[i].magnitude > fourier_chords.spectrum_data[i - 1].magnitude
-            && fourier_chords.spectrum_data[i].magnitude > fourier_chords.spectrum_data[i + 1].magnitude
-        {
-            fourier_chords.local_maxima.push(fourier_chords.spectrum_data[i].clone());
-        }
+// One sample of a bus reduced to a single channel. Channels beyond the bus's last one select the
+// last channel; the mixdown is averaged so that its level matches a single channel's. A bus
+// without channels is silent.
+fn bus_sample(channels: &[&mut [f32]], channel_mode: ChannelMode, channel: usize, i: usize) -> f32 {
+    let last = match channels.last() {
+        Some(last) => last,
+        None => return 0.0,
+    };
+
+    match channel_mode {
+        ChannelMode::Single => channels.get(channel).unwrap_or(last)[i],
+        ChannelMode::Mixdown => channels.iter().map(|channel| channel[i]).sum::<f32>() / channels.len() as f32,
     }
 }
 
-// Identifies maximum magnitude from SpectrumData vector
-// TODO: Error handling
-fn max_magnitude(spectrum: &Vec<SpectrumData>) -> f32 {
-    spectrum.iter()
-        .map(|data| data.magnitude) // Extract the magnitude from each SpectrumData
-        .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal)) // Compare magnitudes
-        .unwrap_or(10000.0) // Handle the case where spectrum_data is empty
+// Capacity of the sample queue at a sample rate
+fn sample_queue_capacity(sample_rate: f32) -> usize {
+    (SAMPLE_QUEUE_SECONDS * sample_rate) as usize
 }
 
-// TODO: Implement prominent peak picking algorithm
-fn get_prominent_peaks(fourier_chords: &mut FourierChords) -> () {
-    fourier_chords.prominence_threshold = max_magnitude(&fourier_chords.local_maxima) / 4.0;
-
-    for peak in &fourier_chords.local_maxima {
-        fourier_chords.prominence = calculate_prominence(&fourier_chords.spectrum_data, peak.index);
-        if fourier_chords.prominence >= fourier_chords.prominence_threshold {
-            fourier_chords.prominent_peaks.push(peak.clone());
-        }
-    }
+// Number of samples between analysis tasks at a sample rate
+fn analysis_hop(sample_rate: f32) -> usize {
+    ((ANALYSIS_INTERVAL_MS * sample_rate / 1000.0).round() as usize).max(1)
 }
 
-fn calculate_prominence(spectrum: &[SpectrumData], peak_index: usize) -> f32 {
-    // Find the lowest contour line around the peak
-    let mut left_min = f32::MAX;
-    let mut right_min = f32::MAX;
-
-    // Find the left minimum by iterating to the left of the peak until we find a peak that's
-    // higher or end of spectrum is reached
-    for i in (0..peak_index).rev() {
-        if spectrum[i].magnitude > spectrum[peak_index].magnitude {
-            break;
-        }
-
-        left_min = right_min.min(spectrum[i].magnitude);
-    }
-
-    for i in peak_index + 1..spectrum.len() {
-        if spectrum[i].magnitude > spectrum[peak_index].magnitude {
-            break;
-        }
-        right_min = right_min.min(spectrum[i].magnitude);
-    }
-
-    // The prominence is the height of the peak minus the maximum of the left and right minima (since we want the higher trough)
-    let prominence = spectrum[peak_index].magnitude - left_min.max(right_min);
-
-    // Return prominence
-    prominence
+// Spectrum Data Structure Definition
+#[derive(Debug, Clone)]
+pub struct SpectrumData {
+    pub frequency: f32,
+    // Squared magnitude of the bin
+    pub power: f32,
 }
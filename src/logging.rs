@@ -0,0 +1,406 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::presets::config_dir;
+
+// Records each logger can queue before new ones are dropped
+const LOG_QUEUE_CAPACITY: usize = 256;
+
+// Records kept for the diagnostics panel
+const LOG_HISTORY_CAPACITY: usize = 512;
+
+// Longest message a record can hold in bytes. Longer messages are cut off and end in TRUNCATED.
+const MESSAGE_CAPACITY: usize = 240;
+
+// Marks a message that was cut off
+const TRUNCATED: &str = "…";
+
+// Name of the log file in the configuration folder
+const LOG_FILE_NAME: &str = "Fourier Chords.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 4] = [LogLevel::Debug, LogLevel::Info, LogLevel::Warning, LogLevel::Error];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "Debug",
+            LogLevel::Info => "Info",
+            LogLevel::Warning => "Warning",
+            LogLevel::Error => "Error",
+        }
+    }
+}
+
+// What a record is about, so that the diagnostics panel can show one kind of record at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogCategory {
+    // How long the analysis takes
+    Timing,
+
+    // Detection settings: thresholds, frequency range and mode
+    Thresholds,
+
+    // Host buffer sizes and the sample queue
+    Buffers,
+
+    // Sample rate, layout and transport resets
+    Host,
+
+    // Invalid input, such as NaN samples, and frames the analysis had to skip
+    Input,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 5] = [
+        LogCategory::Timing,
+        LogCategory::Thresholds,
+        LogCategory::Buffers,
+        LogCategory::Host,
+        LogCategory::Input,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogCategory::Timing => "Timing",
+            LogCategory::Thresholds => "Thresholds",
+            LogCategory::Buffers => "Buffers",
+            LogCategory::Host => "Host",
+            LogCategory::Input => "Input",
+        }
+    }
+}
+
+// One log message. The text is stored inline, so that records can be created and queued on the
+// audio thread without allocating.
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+    // Seconds since the log was created
+    pub time: f64,
+
+    pub level: LogLevel,
+    pub category: LogCategory,
+
+    message: [u8; MESSAGE_CAPACITY],
+    length: usize,
+    truncated: bool,
+}
+
+impl LogRecord {
+    fn new(time: f64, level: LogLevel, category: LogCategory, message: fmt::Arguments) -> Self {
+        let mut record = Self {
+            time,
+            level,
+            category,
+            message: [0; MESSAGE_CAPACITY],
+            length: 0,
+            truncated: false,
+        };
+        let _ = fmt::Write::write_fmt(&mut record, message);
+        record
+    }
+
+    pub fn message(&self) -> &str {
+        std::str::from_utf8(&self.message[..self.length]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for LogRecord {
+    // Appends the text if it fits. Otherwise the message is cut at a character boundary where
+    // TRUNCATED still fits, and everything written after that is ignored.
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+
+        if text.len() <= MESSAGE_CAPACITY - self.length {
+            self.message[self.length..self.length + text.len()].copy_from_slice(text.as_bytes());
+            self.length += text.len();
+            return Ok(());
+        }
+
+        // Earlier text may already reach into the space TRUNCATED needs
+        let keep = MESSAGE_CAPACITY - TRUNCATED.len();
+        while self.length > keep || !is_char_start(&self.message, self.length) {
+            self.length -= 1;
+        }
+
+        let mut end = (keep - self.length).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        for part in [&text.as_bytes()[..end], TRUNCATED.as_bytes()] {
+            self.message[self.length..self.length + part.len()].copy_from_slice(part);
+            self.length += part.len();
+        }
+        self.truncated = true;
+        Ok(())
+    }
+}
+
+// Whether a UTF-8 character starts at index, or index is the end of the message
+fn is_char_start(message: &[u8], index: usize) -> bool {
+    message.get(index).is_none_or(|&byte| byte & 0xC0 != 0x80)
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:9.3}] {:<7} {:<10} {}", self.time, self.level.name(), self.category.name(), self.message())
+    }
+}
+
+// Write side of the log for one thread. Logging never blocks or allocates, so it is safe on the
+// audio thread; when the queue is full the record is dropped and counted instead.
+pub struct Logger {
+    producer: rtrb::Producer<LogRecord>,
+    start: Instant,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Logger {
+    // Queues a message, e.g. logger.log(LogLevel::Info, LogCategory::Host, format_args!("..."))
+    pub fn log(&mut self, level: LogLevel, category: LogCategory, message: fmt::Arguments) {
+        let record = LogRecord::new(self.start.elapsed().as_secs_f64(), level, category, message);
+        if self.producer.push(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Read side of the log. Collects the records of every logger into a bounded history for the
+// diagnostics panel and, if enabled, appends them to a log file. Only used from non-realtime
+// threads: the background task after each analysis, and the editor.
+pub struct LogCollector {
+    consumers: Vec<rtrb::Consumer<LogRecord>>,
+    start: Instant,
+    dropped: Arc<AtomicUsize>,
+
+    // Most recent records, oldest first
+    history: VecDeque<LogRecord>,
+
+    // Log file, while writing to it is enabled
+    file: Option<BufWriter<File>>,
+}
+
+impl Default for LogCollector {
+    fn default() -> Self {
+        Self {
+            consumers: Vec::new(),
+            start: Instant::now(),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            history: VecDeque::with_capacity(LOG_HISTORY_CAPACITY),
+            file: None,
+        }
+    }
+}
+
+impl LogCollector {
+    // Creates a logger for one more thread
+    pub fn logger(&mut self) -> Logger {
+        let (producer, consumer) = rtrb::RingBuffer::new(LOG_QUEUE_CAPACITY);
+        self.consumers.push(consumer);
+
+        Logger {
+            producer,
+            start: self.start,
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    // Moves every queued record into the history and the log file. Records from different
+    // loggers are interleaved per call rather than sorted by time.
+    pub fn collect(&mut self) {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let record = LogRecord::new(self.start.elapsed().as_secs_f64(),
+                                        LogLevel::Warning,
+                                        LogCategory::Buffers,
+                                        format_args!("{} log records were dropped", dropped));
+            add_record(&mut self.history, &mut self.file, record);
+        }
+
+        for consumer in self.consumers.iter_mut() {
+            while let Ok(record) = consumer.pop() {
+                add_record(&mut self.history, &mut self.file, record);
+            }
+        }
+
+        // A failed write turns the file off rather than failing again for every record
+        if let Some(file) = &mut self.file {
+            if file.flush().is_err() {
+                self.file = None;
+            }
+        }
+    }
+
+    // Adds a record straight to the history, for threads that may block
+    pub fn record(&mut self, level: LogLevel, category: LogCategory, message: fmt::Arguments) {
+        let record = LogRecord::new(self.start.elapsed().as_secs_f64(), level, category, message);
+        add_record(&mut self.history, &mut self.file, record);
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &LogRecord> {
+        self.history.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn is_writing_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // Starts appending records to the log file in the configuration folder, and returns its path
+    pub fn start_file(&mut self) -> io::Result<PathBuf> {
+        let dir = config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no configuration folder found"))?;
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(LOG_FILE_NAME);
+        self.start_file_at(&path)?;
+        Ok(path)
+    }
+
+    // Starts appending records to a file of the caller's choosing
+    pub fn start_file_at(&mut self, path: &Path) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    pub fn stop_file(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+        }
+    }
+}
+
+// Appends a record to the bounded history and the log file, if one is open
+fn add_record(history: &mut VecDeque<LogRecord>, file: &mut Option<BufWriter<File>>, record: LogRecord) {
+    if let Some(writer) = file {
+        if writeln!(writer, "{}", record).is_err() {
+            *file = None;
+        }
+    }
+
+    if history.len() == LOG_HISTORY_CAPACITY {
+        history.pop_front();
+    }
+    history.push_back(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(collector: &LogCollector) -> Vec<String> {
+        collector.history().map(|record| record.message().to_string()).collect()
+    }
+
+    #[test]
+    fn messages_that_fit_are_kept_whole() {
+        let mut collector = LogCollector::default();
+        let text = "x".repeat(MESSAGE_CAPACITY);
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("{}", text));
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("{} of {}", 3, "parts"));
+
+        assert_eq!(messages(&collector), [text, "3 of parts".to_string()]);
+    }
+
+    #[test]
+    fn long_messages_end_in_an_ellipsis() {
+        let mut collector = LogCollector::default();
+
+        // In one piece, in many pieces, and with multi-byte characters across the cut
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("{}", "a".repeat(MESSAGE_CAPACITY + 1)));
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("{}{}{}", "b".repeat(MESSAGE_CAPACITY - 1), "cc", "dd"));
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("{}", "é".repeat(MESSAGE_CAPACITY)));
+
+        for message in messages(&collector) {
+            assert!(message.len() <= MESSAGE_CAPACITY, "{}", message.len());
+            assert!(message.ends_with(TRUNCATED), "{}", message);
+        }
+
+        let messages = messages(&collector);
+        assert_eq!(messages[0], format!("{}{}", "a".repeat(MESSAGE_CAPACITY - TRUNCATED.len()), TRUNCATED));
+        assert_eq!(messages[1], format!("{}{}", "b".repeat(MESSAGE_CAPACITY - TRUNCATED.len()), TRUNCATED));
+        assert!(messages[2].trim_end_matches(TRUNCATED).chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn full_queues_drop_records_and_count_them() {
+        let mut collector = LogCollector::default();
+        let mut logger = collector.logger();
+
+        for i in 0..LOG_QUEUE_CAPACITY + 10 {
+            logger.log(LogLevel::Debug, LogCategory::Timing, format_args!("record {}", i));
+        }
+        collector.collect();
+
+        // The warning comes first, followed by every record that fit, in order
+        let collected = messages(&collector);
+        assert_eq!(collected.len(), LOG_QUEUE_CAPACITY + 1);
+        assert_eq!(collected[0], "10 log records were dropped");
+        assert_eq!(collected[1], "record 0");
+        assert_eq!(collected[LOG_QUEUE_CAPACITY], format!("record {}", LOG_QUEUE_CAPACITY - 1));
+
+        // The count starts over once reported
+        logger.log(LogLevel::Debug, LogCategory::Timing, format_args!("after"));
+        collector.collect();
+        let later = &messages(&collector)[collected.len()..];
+        assert_eq!(later, ["after"]);
+    }
+
+    #[test]
+    fn history_keeps_the_most_recent_records() {
+        let mut collector = LogCollector::default();
+        for i in 0..LOG_HISTORY_CAPACITY + 5 {
+            collector.record(LogLevel::Info, LogCategory::Host, format_args!("{}", i));
+        }
+
+        let messages = messages(&collector);
+        assert_eq!(messages.len(), LOG_HISTORY_CAPACITY);
+        assert_eq!(messages[0], "5");
+    }
+
+    #[test]
+    fn file_sink_appends_collected_records() {
+        let path = std::env::temp_dir().join(format!("fourier-chords-log-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut collector = LogCollector::default();
+        let mut logger = collector.logger();
+        collector.start_file_at(&path).unwrap();
+        assert!(collector.is_writing_file());
+
+        logger.log(LogLevel::Warning, LogCategory::Input, format_args!("from the queue"));
+        collector.collect();
+        collector.record(LogLevel::Error, LogCategory::Host, format_args!("straight in"));
+        collector.stop_file();
+        assert!(!collector.is_writing_file());
+
+        // Records after stopping stay out of the file
+        collector.record(LogLevel::Info, LogCategory::Host, format_args!("not written"));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("Warning") && lines[0].contains("Input") && lines[0].ends_with("from the queue"));
+        assert!(lines[1].contains("Error") && lines[1].contains("Host") && lines[1].ends_with("straight in"));
+    }
+}
@@ -0,0 +1,241 @@
+// Level reported where nothing has been measured yet, in dBFS
+const FLOOR_DB: f32 = -120.0;
+
+// Fewest bins a band may hold. At low frequencies a fraction of an octave spans only a few bins,
+// and a single partial would then decide the band's median.
+const MIN_BAND_BINS: usize = 32;
+
+// Settings for the noise floor estimator
+#[derive(Debug, Clone)]
+pub struct NoiseFloorConfig {
+    // Width of the bands the floor is measured in
+    pub bands_per_octave: f32,
+
+    // Lowest frequency that gets its own band. Bins below it share the first band's floor.
+    pub min_frequency: f32,
+
+    // How fast the floor may rise, in dB per second. It falls immediately, so short pauses reveal
+    // the floor while sustained notes cannot lift it much (minimum statistics).
+    pub rise_db_per_second: f32,
+}
+
+impl Default for NoiseFloorConfig {
+    fn default() -> Self {
+        Self {
+            bands_per_octave: 3.0,
+            min_frequency: 20.0,
+            rise_db_per_second: 3.0,
+        }
+    }
+}
+
+// Frequency-dependent noise floor of a magnitude spectrum. Each frame, the spectrum is split into
+// log-spaced bands and the median level of every band is taken as its floor, so that the few bins
+// occupied by notes do not count. Band floors are tracked over time and interpolated across bins.
+pub struct NoiseFloorEstimator {
+    config: NoiseFloorConfig,
+
+    // First bin of every band, followed by the end of the last band
+    band_edges: Vec<usize>,
+
+    // Tracked floor of every band in dBFS
+    band_floor_db: Vec<f32>,
+
+    // Estimated floor of every bin in dBFS
+    floor_db: Vec<f32>,
+
+    // Scratch space for the band medians
+    scratch: Vec<f32>,
+
+    // False until the first frame has been measured
+    initialized: bool,
+}
+
+impl NoiseFloorEstimator {
+    // Sets up the bands for a spectrum of num_bins bins spaced bin_width Hz apart, starting at 0 Hz
+    pub fn new(config: NoiseFloorConfig, num_bins: usize, bin_width: f32) -> Self {
+        let band_ratio = (1.0 / config.bands_per_octave.max(0.1)).exp2();
+        let first_bin = ((config.min_frequency / bin_width).ceil() as usize).clamp(1, num_bins.max(1));
+
+        let mut band_edges = vec![first_bin];
+        let mut edge = first_bin;
+        while edge < num_bins {
+            let next = ((edge as f32 * band_ratio).ceil() as usize).max(edge + MIN_BAND_BINS);
+            edge = next.min(num_bins);
+            band_edges.push(edge);
+        }
+
+        // A trailing band that is too narrow is merged into the one before it
+        if band_edges.len() > 2 && band_edges[band_edges.len() - 1] - band_edges[band_edges.len() - 2] < MIN_BAND_BINS {
+            band_edges.remove(band_edges.len() - 2);
+        }
+
+        let num_bands = band_edges.len().saturating_sub(1);
+        let widest_band = band_edges.windows(2).map(|edges| edges[1] - edges[0]).max().unwrap_or(0);
+
+        Self {
+            config,
+            band_edges,
+            band_floor_db: vec![FLOOR_DB; num_bands],
+            floor_db: vec![FLOOR_DB; num_bins],
+            scratch: Vec::with_capacity(widest_band),
+            initialized: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.band_floor_db.iter_mut().for_each(|floor| *floor = FLOOR_DB);
+        self.floor_db.iter_mut().for_each(|floor| *floor = FLOOR_DB);
+        self.initialized = false;
+    }
+
+    // Estimated floor of every bin in dBFS
+    pub fn floor_db(&self) -> &[f32] {
+        &self.floor_db
+    }
+
+    // Measures one frame. `levels_db` holds the level of every bin in dBFS and must be as long as
+    // the spectrum the estimator was created for; `frame_duration` is the time since the previous
+    // frame in seconds.
+    pub fn update(&mut self, levels_db: &[f32], frame_duration: f32) {
+        let max_rise = self.config.rise_db_per_second * frame_duration;
+
+        for (band, edges) in self.band_edges.windows(2).enumerate() {
+            let (start, end) = (edges[0], edges[1].min(levels_db.len()));
+            if start >= end {
+                continue;
+            }
+
+            self.scratch.clear();
+            self.scratch.extend_from_slice(&levels_db[start..end]);
+            let middle = self.scratch.len() / 2;
+            let (_, &mut median, _) = self.scratch.select_nth_unstable_by(middle, f32::total_cmp);
+
+            let floor = &mut self.band_floor_db[band];
+            *floor = if !self.initialized || median < *floor {
+                median
+            } else {
+                (*floor + max_rise).min(median)
+            };
+        }
+        self.initialized = true;
+
+        self.interpolate_bins();
+    }
+
+    // Spreads the band floors over the bins by interpolating between band centres in log frequency
+    fn interpolate_bins(&mut self) {
+        let num_bands = self.band_floor_db.len();
+        if num_bands == 0 {
+            return;
+        }
+
+        let centre = |band: usize| ((self.band_edges[band] * self.band_edges[band + 1]) as f32).sqrt().log2();
+
+        let mut band = 0;
+        for (bin, floor) in self.floor_db.iter_mut().enumerate() {
+            let position = (bin.max(1) as f32).log2();
+
+            while band + 1 < num_bands && position > centre(band + 1) {
+                band += 1;
+            }
+
+            *floor = if band + 1 == num_bands || position <= centre(band) {
+                self.band_floor_db[band]
+            } else {
+                let (low, high) = (centre(band), centre(band + 1));
+                let amount = (position - low) / (high - low);
+                self.band_floor_db[band] + amount * (self.band_floor_db[band + 1] - self.band_floor_db[band])
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUM_BINS: usize = 4097;
+    const BIN_WIDTH: f32 = 48000.0 / 8192.0;
+
+    fn estimator() -> NoiseFloorEstimator {
+        NoiseFloorEstimator::new(NoiseFloorConfig::default(), NUM_BINS, BIN_WIDTH)
+    }
+
+    fn assert_floor(estimator: &NoiseFloorEstimator, bins: std::ops::Range<usize>, expected: f32) {
+        for bin in bins {
+            let floor = estimator.floor_db()[bin];
+            assert!((floor - expected).abs() < 1e-3, "bin {} has a floor of {} instead of {}", bin, floor, expected);
+        }
+    }
+
+    #[test]
+    fn flat_noise_is_its_own_floor() {
+        let mut estimator = estimator();
+        estimator.update(&[-60.0; NUM_BINS], 0.1);
+
+        assert_floor(&estimator, 0..NUM_BINS, -60.0);
+    }
+
+    #[test]
+    fn partials_do_not_lift_the_floor() {
+        // Harmonics of 110 Hz at -10 dBFS over noise at -70 dBFS
+        let mut levels = [-70.0; NUM_BINS];
+        for harmonic in 1..=40 {
+            let bin = (110.0 * harmonic as f32 / BIN_WIDTH).round() as usize;
+            levels[bin - 1..=bin + 1].iter_mut().for_each(|level| *level = -10.0);
+        }
+
+        let mut estimator = estimator();
+        estimator.update(&levels, 0.1);
+
+        assert_floor(&estimator, 0..NUM_BINS, -70.0);
+    }
+
+    #[test]
+    fn floor_falls_at_once_and_rises_slowly() {
+        let mut estimator = estimator();
+        estimator.update(&[-50.0; NUM_BINS], 0.1);
+        estimator.update(&[-80.0; NUM_BINS], 0.1);
+        assert_floor(&estimator, 0..NUM_BINS, -80.0);
+
+        // 3 dB per second for a tenth of a second per frame
+        for frame in 1..=10 {
+            estimator.update(&[-50.0; NUM_BINS], 0.1);
+            assert_floor(&estimator, 0..NUM_BINS, -80.0 + 0.3 * frame as f32);
+        }
+
+        // The rise stops at the measured level
+        estimator.update(&[-76.5; NUM_BINS], 2.0);
+        assert_floor(&estimator, 0..NUM_BINS, -76.5);
+    }
+
+    #[test]
+    fn floor_follows_the_spectral_tilt() {
+        // Noise that falls by 6 dB per octave above 100 Hz
+        let levels: Vec<f32> = (0..NUM_BINS)
+            .map(|bin| -40.0 - 6.0 * (bin as f32 * BIN_WIDTH / 100.0).max(1.0).log2())
+            .collect();
+
+        let mut estimator = estimator();
+        estimator.update(&levels, 0.1);
+        let floor = estimator.floor_db();
+
+        assert!(floor.windows(2).all(|pair| pair[1] <= pair[0] + 1e-3));
+        for bin in [NUM_BINS / 8, NUM_BINS / 4, NUM_BINS / 2, NUM_BINS - 1] {
+            assert!((floor[bin] - levels[bin]).abs() < 1.5, "bin {}: {} vs {}", bin, floor[bin], levels[bin]);
+        }
+    }
+
+    #[test]
+    fn reset_forgets_the_floor() {
+        let mut estimator = estimator();
+        estimator.update(&[-80.0; NUM_BINS], 0.1);
+        estimator.reset();
+        assert_floor(&estimator, 0..NUM_BINS, FLOOR_DB);
+
+        // The first frame after a reset is taken as is instead of rising slowly
+        estimator.update(&[-40.0; NUM_BINS], 0.1);
+        assert_floor(&estimator, 0..NUM_BINS, -40.0);
+    }
+}
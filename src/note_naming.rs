@@ -0,0 +1,307 @@
+use crate::chord_recognition::Chord;
+use crate::{Key, NamingSystem, Spelling};
+
+// Pitch class of each natural note, starting at C
+const LETTER_PITCH_CLASSES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+// Names of the natural notes in each system, starting at C
+const LETTER_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+const SOLFEGE_NAMES: [&str; 7] = ["Do", "Re", "Mi", "Fa", "Sol", "La", "Si"];
+const GERMAN_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "H"];
+
+// Letter and accidental of every pitch class when spelled with sharps or with flats
+const SHARP_SPELLINGS: [(usize, i32); 12] = [
+    (0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (3, 0), (3, 1), (4, 0), (4, 1), (5, 0), (5, 1), (6, 0),
+];
+const FLAT_SPELLINGS: [(usize, i32); 12] = [
+    (0, 0), (1, -1), (1, 0), (2, -1), (2, 0), (3, 0), (4, -1), (4, 0), (5, -1), (5, 0), (6, -1), (6, 0),
+];
+
+// Scale degree (0 is the tonic) and alteration of every interval above the tonic in a major key.
+// Notes outside the scale are spelled as the common chromatic degrees b2, b3, #4, b6 and b7.
+const KEY_DEGREES: [(usize, i32); 12] = [
+    (0, 0), (1, -1), (1, 0), (2, -1), (2, 0), (3, 0), (3, 1), (4, 0), (5, -1), (5, 0), (6, -1), (6, 0),
+];
+
+// A pitch class written as a natural note and an accidental
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpelledPitch {
+    // Natural note, where 0 is C and 6 is B
+    pub letter: usize,
+
+    // Sharps (positive) or flats (negative) applied to the letter
+    pub accidental: i32,
+}
+
+impl SpelledPitch {
+    // Spells a pitch class on a given letter, with whatever accidental that takes
+    fn on_letter(pitch_class: usize, letter: usize) -> Self {
+        let accidental = (pitch_class as i32 - LETTER_PITCH_CLASSES[letter]).rem_euclid(12);
+        Self {
+            letter,
+            accidental: if accidental > 6 { accidental - 12 } else { accidental },
+        }
+    }
+
+    fn pitch_class(&self) -> usize {
+        (LETTER_PITCH_CLASSES[self.letter] + self.accidental).rem_euclid(12) as usize
+    }
+}
+
+// How note and chord names are written. Built from the editor's naming parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteNaming {
+    pub system: NamingSystem,
+    pub spelling: Spelling,
+    pub key: Key,
+}
+
+impl NoteNaming {
+    // Name of a MIDI note, with its octave for every system except Nashville numbers
+    pub fn note_name(&self, midi_note: usize) -> String {
+        let name = self.pitch_class_name(midi_note % 12);
+        if self.system == NamingSystem::Nashville {
+            return name;
+        }
+
+        // The octave belongs to the letter, so B#3 and Cb5 sit next to C4 and B4
+        let spelled = self.spell(midi_note % 12);
+        let octave = (midi_note as i32 - spelled.accidental).div_euclid(12) - 1;
+        format!("{}{}", name, octave)
+    }
+
+    // Name of a pitch class, where 0 is C
+    pub fn pitch_class_name(&self, pitch_class: usize) -> String {
+        match self.system {
+            NamingSystem::Letters => with_accidentals(LETTER_NAMES, self.spell(pitch_class)),
+            NamingSystem::Solfege => with_accidentals(SOLFEGE_NAMES, self.spell(pitch_class)),
+            NamingSystem::German => german_name(self.spell(pitch_class)),
+            NamingSystem::Nashville => nashville_number(pitch_class, self.key),
+        }
+    }
+
+    pub fn chord_name(&self, chord: &Chord) -> String {
+        format!("{}{}", self.pitch_class_name(chord.root), chord.quality.suffix())
+    }
+
+    // Letter and accidental of a pitch class under the selected spelling
+    pub fn spell(&self, pitch_class: usize) -> SpelledPitch {
+        let (letter, accidental) = match self.spelling {
+            Spelling::Sharps => SHARP_SPELLINGS[pitch_class % 12],
+            Spelling::Flats => FLAT_SPELLINGS[pitch_class % 12],
+            Spelling::Key => return spell_in_key(pitch_class % 12, self.key),
+        };
+        SpelledPitch { letter, accidental }
+    }
+}
+
+impl Default for NoteNaming {
+    fn default() -> Self {
+        Self {
+            system: NamingSystem::Letters,
+            spelling: Spelling::Sharps,
+            key: Key::C,
+        }
+    }
+}
+
+// Tonic of a major key. Relative minors share the key signature.
+fn key_tonic(key: Key) -> SpelledPitch {
+    let (letter, accidental) = match key {
+        Key::C => (0, 0),
+        Key::Db => (1, -1),
+        Key::D => (1, 0),
+        Key::Eb => (2, -1),
+        Key::E => (2, 0),
+        Key::F => (3, 0),
+        Key::FSharp => (3, 1),
+        Key::Gb => (4, -1),
+        Key::G => (4, 0),
+        Key::Ab => (5, -1),
+        Key::A => (5, 0),
+        Key::Bb => (6, -1),
+        Key::B => (6, 0),
+    };
+    SpelledPitch { letter, accidental }
+}
+
+// Spells a pitch class as a degree of the key, so that Bb is Bb in F major and A# is A# in B major.
+// A chromatic degree is written as its neighbouring degree instead when that avoids a double
+// accidental or gives a natural, e.g. G rather than Abb in Gb major and E rather than Fb in Eb
+// major.
+fn spell_in_key(pitch_class: usize, key: Key) -> SpelledPitch {
+    let tonic = key_tonic(key);
+    let interval = (pitch_class + 12 - tonic.pitch_class()) % 12;
+    let (degree, alteration) = KEY_DEGREES[interval];
+
+    let spelled = SpelledPitch::on_letter(pitch_class, (tonic.letter + degree) % 7);
+    if alteration == 0 {
+        return spelled;
+    }
+
+    let neighbour_degree = if alteration < 0 { degree + 6 } else { degree + 1 };
+    let neighbour = SpelledPitch::on_letter(pitch_class, (tonic.letter + neighbour_degree) % 7);
+
+    if spelled.accidental.abs() > 1 || (spelled.accidental != 0 && neighbour.accidental == 0) {
+        neighbour
+    } else {
+        spelled
+    }
+}
+
+fn with_accidentals(names: [&str; 7], spelled: SpelledPitch) -> String {
+    let accidental = if spelled.accidental > 0 { "#" } else { "b" };
+    format!("{}{}", names[spelled.letter], accidental.repeat(spelled.accidental.unsigned_abs() as usize))
+}
+
+// German names: H is B natural and B is B flat; sharps add "is" and flats add "es", which is
+// shortened to "s" after E and A
+fn german_name(spelled: SpelledPitch) -> String {
+    let mut name = GERMAN_NAMES[spelled.letter].to_string();
+
+    if spelled.accidental > 0 {
+        name.push_str(&"is".repeat(spelled.accidental as usize));
+    } else if spelled.accidental < 0 {
+        let flats = spelled.accidental.unsigned_abs() as usize;
+        match spelled.letter {
+            6 if flats == 1 => name = "B".to_string(),
+            2 | 5 => name.push_str(&format!("s{}", "es".repeat(flats - 1))),
+            _ => name.push_str(&"es".repeat(flats)),
+        }
+    }
+
+    name
+}
+
+// Scale degree of a pitch class in the key, e.g. "4" or "b7"
+fn nashville_number(pitch_class: usize, key: Key) -> String {
+    let interval = (pitch_class + 12 - key_tonic(key).pitch_class()) % 12;
+    let (degree, alteration) = KEY_DEGREES[interval];
+
+    let accidental = match alteration {
+        1 => "#",
+        -1 => "b",
+        _ => "",
+    };
+    format!("{}{}", accidental, degree + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chord_recognition::ChordQuality;
+
+    fn naming(system: NamingSystem, spelling: Spelling, key: Key) -> NoteNaming {
+        NoteNaming { system, spelling, key }
+    }
+
+    #[test]
+    fn notes_are_spelled_with_sharps_or_flats() {
+        let cases = [
+            (Spelling::Sharps, 60, "C4"),
+            (Spelling::Sharps, 61, "C#4"),
+            (Spelling::Sharps, 70, "A#4"),
+            (Spelling::Sharps, 21, "A0"),
+            (Spelling::Flats, 61, "Db4"),
+            (Spelling::Flats, 70, "Bb4"),
+            (Spelling::Flats, 66, "Gb4"),
+            (Spelling::Flats, 108, "C8"),
+        ];
+
+        for (spelling, midi_note, expected) in cases {
+            let name = naming(NamingSystem::Letters, spelling, Key::C).note_name(midi_note);
+            assert_eq!(name, expected, "{:?} {}", spelling, midi_note);
+        }
+    }
+
+    #[test]
+    fn key_spelling_follows_the_key_signature() {
+        let cases = [
+            // Diatonic notes take the key's accidentals
+            (Key::F, 10, "Bb"),
+            (Key::B, 10, "A#"),
+            (Key::D, 6, "F#"),
+            (Key::Ab, 1, "Db"),
+            // Chromatic notes are the common altered degrees
+            (Key::C, 1, "Db"),
+            (Key::C, 3, "Eb"),
+            (Key::C, 6, "F#"),
+            (Key::C, 8, "Ab"),
+            (Key::C, 10, "Bb"),
+            // A natural is preferred to a flat on the next letter
+            (Key::Eb, 4, "E"),
+            (Key::Db, 11, "B"),
+            // Keys with six sharps or flats need E# and Cb
+            (Key::FSharp, 5, "E#"),
+            (Key::Gb, 11, "Cb"),
+        ];
+
+        for (key, pitch_class, expected) in cases {
+            let name = naming(NamingSystem::Letters, Spelling::Key, key).pitch_class_name(pitch_class);
+            assert_eq!(name, expected, "{:?} {}", key, pitch_class);
+        }
+    }
+
+    #[test]
+    fn octaves_belong_to_the_letter() {
+        assert_eq!(naming(NamingSystem::Letters, Spelling::Key, Key::Gb).note_name(71), "Cb5");
+        assert_eq!(naming(NamingSystem::Letters, Spelling::Key, Key::FSharp).note_name(65), "E#4");
+        assert_eq!(naming(NamingSystem::Letters, Spelling::Key, Key::FSharp).note_name(66), "F#4");
+    }
+
+    #[test]
+    fn solfege_and_german_names() {
+        let cases = [
+            (NamingSystem::Solfege, Spelling::Sharps, Key::C, 60, "Do4"),
+            (NamingSystem::Solfege, Spelling::Sharps, Key::C, 61, "Do#4"),
+            (NamingSystem::Solfege, Spelling::Sharps, Key::C, 67, "Sol4"),
+            (NamingSystem::Solfege, Spelling::Flats, Key::C, 70, "Sib4"),
+            (NamingSystem::German, Spelling::Sharps, Key::C, 71, "H4"),
+            (NamingSystem::German, Spelling::Flats, Key::C, 70, "B4"),
+            (NamingSystem::German, Spelling::Sharps, Key::C, 70, "Ais4"),
+            (NamingSystem::German, Spelling::Sharps, Key::C, 66, "Fis4"),
+            (NamingSystem::German, Spelling::Flats, Key::C, 61, "Des4"),
+            (NamingSystem::German, Spelling::Flats, Key::C, 63, "Es4"),
+            (NamingSystem::German, Spelling::Flats, Key::C, 68, "As4"),
+            (NamingSystem::German, Spelling::Key, Key::Gb, 71, "Ces5"),
+            (NamingSystem::German, Spelling::Key, Key::FSharp, 65, "Eis4"),
+        ];
+
+        for (system, spelling, key, midi_note, expected) in cases {
+            let name = naming(system, spelling, key).note_name(midi_note);
+            assert_eq!(name, expected, "{:?} {:?} {:?} {}", system, spelling, key, midi_note);
+        }
+    }
+
+    #[test]
+    fn nashville_numbers_are_scale_degrees() {
+        let cases = [(7, "1"), (9, "2"), (11, "3"), (0, "4"), (2, "5"), (4, "6"), (6, "7"), (5, "b7"), (10, "b3"), (1, "#4")];
+
+        let nashville = naming(NamingSystem::Nashville, Spelling::Sharps, Key::G);
+        for (pitch_class, expected) in cases {
+            assert_eq!(nashville.pitch_class_name(pitch_class), expected, "{}", pitch_class);
+        }
+
+        // Numbers have no octave
+        assert_eq!(nashville.note_name(67), "1");
+    }
+
+    #[test]
+    fn chords_are_named_in_the_selected_system() {
+        let chord = |root, quality| Chord { root, quality };
+
+        let cases = [
+            (naming(NamingSystem::Letters, Spelling::Flats, Key::C), chord(10, ChordQuality::Minor), "Bbm"),
+            (naming(NamingSystem::Letters, Spelling::Key, Key::E), chord(1, ChordQuality::Minor7), "C#m7"),
+            (naming(NamingSystem::Solfege, Spelling::Sharps, Key::C), chord(7, ChordQuality::Dominant7), "Sol7"),
+            (naming(NamingSystem::German, Spelling::Sharps, Key::C), chord(11, ChordQuality::Major), "H"),
+            (naming(NamingSystem::German, Spelling::Key, Key::F), chord(10, ChordQuality::Major), "B"),
+            (naming(NamingSystem::Nashville, Spelling::Sharps, Key::C), chord(9, ChordQuality::Minor), "6m"),
+            (naming(NamingSystem::Nashville, Spelling::Sharps, Key::A), chord(2, ChordQuality::Major), "4"),
+        ];
+
+        for (naming, chord, expected) in cases {
+            assert_eq!(naming.chord_name(&chord), expected, "{:?} {:?}", naming, chord);
+        }
+    }
+}
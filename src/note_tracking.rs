@@ -0,0 +1,308 @@
+// Number of tracked pitches, one per MIDI note number
+pub const NUM_PITCHES: usize = 128;
+
+// Level used for pitches that were not detected in a frame
+pub const SILENCE_DB: f32 = -120.0;
+
+// Lifecycle of a single pitch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteState {
+    // Not sounding
+    Off,
+
+    // Crossed the on threshold but has not lasted the minimum duration yet
+    Onset,
+
+    // Sounding and above the off threshold
+    Sustain,
+
+    // Dropped below the off threshold; still reported until the hold time runs out
+    Release,
+}
+
+// Thresholds and timings for the note tracker
+#[derive(Debug, Clone)]
+pub struct NoteTrackerConfig {
+    // Level in dBFS a pitch has to reach to start a note
+    pub on_threshold_db: f32,
+
+    // Level in dBFS below which a sounding note starts releasing. Lower than the on threshold so
+    // that notes hovering around one level do not flicker.
+    pub off_threshold_db: f32,
+
+    // Minimum time in seconds a pitch has to stay above the off threshold before it is reported
+    pub min_duration: f32,
+
+    // Time in seconds a released note keeps being reported before it is switched off
+    pub hold_time: f32,
+}
+
+impl Default for NoteTrackerConfig {
+    fn default() -> Self {
+        Self {
+            on_threshold_db: -48.0,
+            off_threshold_db: -60.0,
+            min_duration: 0.05,
+            hold_time: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PitchState {
+    state: NoteState,
+
+    // Time the current note started
+    onset_time: f32,
+
+    // Last time the pitch was above the off threshold
+    last_active_time: f32,
+}
+
+impl Default for PitchState {
+    fn default() -> Self {
+        Self {
+            state: NoteState::Off,
+            onset_time: 0.0,
+            last_active_time: 0.0,
+        }
+    }
+}
+
+// Tracks every pitch through onset, sustain and release so that only the notes that are
+// sounding now get reported
+pub struct NoteTracker {
+    config: NoteTrackerConfig,
+    pitches: [PitchState; NUM_PITCHES],
+}
+
+impl NoteTracker {
+    pub fn new(config: NoteTrackerConfig) -> Self {
+        Self {
+            config,
+            pitches: [PitchState::default(); NUM_PITCHES],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pitches = [PitchState::default(); NUM_PITCHES];
+    }
+
+    // Changes the levels at which notes start and release, in dBFS. Notes that are sounding keep
+    // their state and are judged against the new levels from the next frame on.
+    pub fn set_thresholds(&mut self, on_threshold_db: f32, off_threshold_db: f32) {
+        self.config.on_threshold_db = on_threshold_db;
+        self.config.off_threshold_db = off_threshold_db.min(on_threshold_db);
+    }
+
+    // Advances every pitch by one analysis frame. `levels` holds the level of each MIDI pitch in
+    // dBFS, `time` is the start of the frame and `frame_duration` its length, both in seconds.
+    pub fn update(&mut self, levels: &[f32; NUM_PITCHES], time: f32, frame_duration: f32) {
+        let config = &self.config;
+        let frame_end = time + frame_duration;
+
+        for (pitch, &level_db) in self.pitches.iter_mut().zip(levels.iter()) {
+            let above_on = level_db >= config.on_threshold_db;
+            let above_off = level_db >= config.off_threshold_db;
+
+            pitch.state = match pitch.state {
+                NoteState::Off if above_on => {
+                    pitch.onset_time = time;
+                    pitch.last_active_time = frame_end;
+                    if frame_end - pitch.onset_time >= config.min_duration {
+                        NoteState::Sustain
+                    } else {
+                        NoteState::Onset
+                    }
+                }
+                NoteState::Off => NoteState::Off,
+                NoteState::Onset if !above_off => NoteState::Off,
+                NoteState::Onset => {
+                    pitch.last_active_time = frame_end;
+                    if frame_end - pitch.onset_time >= config.min_duration {
+                        NoteState::Sustain
+                    } else {
+                        NoteState::Onset
+                    }
+                }
+                // A sounding note stays on while above the off threshold, but a released note has
+                // to reach the on threshold again to come back
+                NoteState::Sustain if above_off => {
+                    pitch.last_active_time = frame_end;
+                    NoteState::Sustain
+                }
+                NoteState::Release if above_on => {
+                    pitch.last_active_time = frame_end;
+                    NoteState::Sustain
+                }
+                NoteState::Sustain | NoteState::Release => {
+                    if frame_end - pitch.last_active_time >= config.hold_time {
+                        NoteState::Off
+                    } else {
+                        NoteState::Release
+                    }
+                }
+            };
+        }
+    }
+
+    // Called on a detected attack. Sounding notes are moved to release so that they disappear
+    // after the hold time unless the next frames confirm them again.
+    pub fn on_onset(&mut self, time: f32) {
+        for pitch in self.pitches.iter_mut() {
+            if pitch.state == NoteState::Sustain {
+                pitch.state = NoteState::Release;
+                pitch.last_active_time = time;
+            }
+        }
+    }
+
+    // True if the pitch should be reported as sounding
+    pub fn is_sounding(&self, midi_note: usize) -> bool {
+        matches!(self.pitches[midi_note].state, NoteState::Sustain | NoteState::Release)
+    }
+
+    // Time in seconds at which the pitch's current note started
+    pub fn onset_time(&self, midi_note: usize) -> f32 {
+        self.pitches[midi_note].onset_time
+    }
+
+    // MIDI note numbers of every sounding pitch, lowest first
+    pub fn sounding_notes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..NUM_PITCHES).filter(move |&midi_note| self.is_sounding(midi_note))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames of 20 ms, so that a note needs three frames to last the default minimum duration
+    const FRAME: f32 = 0.02;
+    const NOTE: usize = 60;
+
+    // A tracker that is advanced one frame at a time, with NOTE at the given levels
+    struct Frames {
+        tracker: NoteTracker,
+        frame: usize,
+    }
+
+    impl Frames {
+        fn new() -> Self {
+            Self { tracker: NoteTracker::new(NoteTrackerConfig::default()), frame: 0 }
+        }
+
+        fn time(&self) -> f32 {
+            self.frame as f32 * FRAME
+        }
+
+        fn push(&mut self, level_db: f32) -> NoteState {
+            let mut levels = [SILENCE_DB; NUM_PITCHES];
+            levels[NOTE] = level_db;
+            self.tracker.update(&levels, self.time(), FRAME);
+            self.frame += 1;
+            self.tracker.pitches[NOTE].state
+        }
+    }
+
+    #[test]
+    fn notes_are_reported_after_the_minimum_duration() {
+        let mut frames = Frames::new();
+        frames.push(SILENCE_DB);
+
+        assert_eq!(frames.push(-40.0), NoteState::Onset);
+        assert!(!frames.tracker.is_sounding(NOTE));
+        assert_eq!(frames.push(-40.0), NoteState::Onset);
+        assert_eq!(frames.push(-40.0), NoteState::Sustain);
+        assert!(frames.tracker.is_sounding(NOTE));
+        assert_eq!(frames.tracker.onset_time(NOTE), FRAME);
+    }
+
+    #[test]
+    fn short_blips_are_never_reported() {
+        let mut frames = Frames::new();
+
+        assert_eq!(frames.push(-40.0), NoteState::Onset);
+        assert_eq!(frames.push(-70.0), NoteState::Off);
+        assert_eq!(frames.tracker.sounding_notes().count(), 0);
+    }
+
+    #[test]
+    fn levels_between_the_thresholds_keep_notes_on_but_do_not_start_them() {
+        let mut frames = Frames::new();
+
+        // -55 dBFS lies between the off (-60) and on (-48) thresholds
+        assert_eq!(frames.push(-55.0), NoteState::Off);
+
+        for _ in 0..3 {
+            frames.push(-40.0);
+        }
+        for _ in 0..10 {
+            assert_eq!(frames.push(-55.0), NoteState::Sustain);
+        }
+    }
+
+    #[test]
+    fn released_notes_are_held_then_switched_off() {
+        let mut frames = Frames::new();
+        for _ in 0..3 {
+            frames.push(-40.0);
+        }
+
+        // The hold time of 250 ms lasts 12 frames after the last active one
+        for _ in 0..12 {
+            assert_eq!(frames.push(-70.0), NoteState::Release);
+            assert!(frames.tracker.is_sounding(NOTE));
+        }
+        assert_eq!(frames.push(-70.0), NoteState::Off);
+        assert!(!frames.tracker.is_sounding(NOTE));
+    }
+
+    #[test]
+    fn released_notes_need_the_on_threshold_to_come_back() {
+        let mut frames = Frames::new();
+        for _ in 0..3 {
+            frames.push(-40.0);
+        }
+
+        assert_eq!(frames.push(-70.0), NoteState::Release);
+        assert_eq!(frames.push(-55.0), NoteState::Release);
+        assert_eq!(frames.push(-40.0), NoteState::Sustain);
+    }
+
+    #[test]
+    fn onsets_release_sounding_notes() {
+        let mut frames = Frames::new();
+        for _ in 0..3 {
+            frames.push(-40.0);
+        }
+
+        let time = frames.time();
+        frames.tracker.on_onset(time);
+        assert_eq!(frames.tracker.pitches[NOTE].state, NoteState::Release);
+
+        // The next frame confirms the note again
+        assert_eq!(frames.push(-40.0), NoteState::Sustain);
+    }
+
+    #[test]
+    fn the_off_threshold_never_exceeds_the_on_threshold() {
+        let mut tracker = NoteTracker::new(NoteTrackerConfig::default());
+        tracker.set_thresholds(-50.0, -30.0);
+        assert_eq!(tracker.config.off_threshold_db, -50.0);
+    }
+
+    #[test]
+    fn sounding_notes_are_listed_lowest_first() {
+        let mut tracker = NoteTracker::new(NoteTrackerConfig::default());
+        let mut levels = [SILENCE_DB; NUM_PITCHES];
+        levels[67] = -30.0;
+        levels[48] = -30.0;
+        levels[64] = -30.0;
+
+        for frame in 0..3 {
+            tracker.update(&levels, frame as f32 * FRAME, FRAME);
+        }
+        assert_eq!(tracker.sounding_notes().collect::<Vec<_>>(), [48, 64, 67]);
+    }
+}
@@ -0,0 +1,339 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::OnsetMethod;
+
+// A detected onset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    // Time of the onset in seconds since the detector was created or reset
+    pub time: f32,
+
+    // Value of the onset detection function at the onset, relative to the adaptive threshold
+    pub strength: f32,
+}
+
+// Settings for the onset detector
+#[derive(Debug, Clone)]
+pub struct OnsetConfig {
+    pub method: OnsetMethod,
+
+    // Length of each analysis frame in milliseconds, zero-padded to the next power of two for the FFT
+    pub frame_length_ms: f32,
+
+    // Time between the start of consecutive frames in milliseconds
+    pub hop_length_ms: f32,
+
+    // Number of past detection function values used for the adaptive (median) threshold
+    pub threshold_window: usize,
+
+    // The detection function has to exceed multiplier * median + offset to count as an onset
+    pub threshold_multiplier: f32,
+    pub threshold_offset: f32,
+
+    // Onsets closer together than this are merged, in seconds
+    pub min_interval: f32,
+}
+
+impl Default for OnsetConfig {
+    fn default() -> Self {
+        Self {
+            method: OnsetMethod::SpectralFlux,
+            // 1023 samples in a 1024 point FFT and a 512 sample hop at 44.1 kHz
+            frame_length_ms: 23.2,
+            hop_length_ms: 11.6,
+            threshold_window: 16,
+            threshold_multiplier: 1.5,
+            threshold_offset: 0.01,
+            min_interval: 0.05,
+        }
+    }
+}
+
+impl OnsetConfig {
+    // Frame length in samples at a sample rate
+    pub fn frame_size(&self, sample_rate: f32) -> usize {
+        ((self.frame_length_ms * sample_rate / 1000.0).round() as usize).max(2)
+    }
+
+    // Hop length in samples at a sample rate
+    pub fn hop_size(&self, sample_rate: f32) -> usize {
+        ((self.hop_length_ms * sample_rate / 1000.0).round() as usize).max(1)
+    }
+}
+
+// Streaming onset detector. Samples are pushed one at a time; every hop a new, overlapping
+// frame is analysed.
+pub struct OnsetDetector {
+    config: OnsetConfig,
+    sample_rate: f32,
+
+    // Frame and hop length in samples at the detector's sample rate, and the power-of-two FFT size
+    // the frame is zero-padded to
+    frame_size: usize,
+    hop_size: usize,
+    fft_size: usize,
+
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+
+    // Circular buffer holding the most recent frame_size samples
+    input: Vec<f32>,
+    input_position: usize,
+    samples_until_hop: usize,
+    samples_seen: u64,
+
+    // FFT scratch buffer
+    spectrum: Vec<Complex<f32>>,
+
+    // Magnitudes and phases of the previous frames, needed by the detection functions
+    previous_magnitudes: Vec<f32>,
+    previous_phases: Vec<f32>,
+    second_previous_phases: Vec<f32>,
+    previous_hfc: f32,
+
+    // Recent detection function values, oldest first, and scratch space for the median
+    detection_history: Vec<f32>,
+    median_scratch: Vec<f32>,
+
+    // Previous two detection function values, to find local maxima
+    previous_value: f32,
+    second_previous_value: f32,
+
+    frames_processed: u64,
+    last_onset_time: f32,
+}
+
+impl OnsetDetector {
+    pub fn new(config: OnsetConfig, sample_rate: f32) -> Self {
+        let frame_size = config.frame_size(sample_rate);
+        let hop_size = config.hop_size(sample_rate);
+        let fft_size = frame_size.next_power_of_two();
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+
+        let window = (0..frame_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (frame_size as f32 - 1.0)).cos())
+            .collect();
+
+        let num_bins = fft_size / 2 + 1;
+
+        Self {
+            sample_rate,
+            frame_size,
+            hop_size,
+            fft_size,
+            fft,
+            window,
+            input: vec![0.0; frame_size],
+            input_position: 0,
+            samples_until_hop: frame_size,
+            samples_seen: 0,
+            spectrum: vec![Complex { re: 0.0, im: 0.0 }; fft_size],
+            previous_magnitudes: vec![0.0; num_bins],
+            previous_phases: vec![0.0; num_bins],
+            second_previous_phases: vec![0.0; num_bins],
+            previous_hfc: 0.0,
+            detection_history: Vec::with_capacity(config.threshold_window),
+            median_scratch: vec![0.0; config.threshold_window],
+            previous_value: 0.0,
+            second_previous_value: 0.0,
+            frames_processed: 0,
+            last_onset_time: f32::NEG_INFINITY,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &OnsetConfig {
+        &self.config
+    }
+
+    // Most recent value of the onset detection function
+    pub fn detection_value(&self) -> f32 {
+        self.previous_value
+    }
+
+    // Number of frames analysed since the detector was created or reset
+    pub fn frames_processed(&self) -> u64 {
+        self.frames_processed
+    }
+
+    // Switches to another detection function. Its history starts over, so the first frames after
+    // the switch cannot report an onset; onset times keep counting from the same origin.
+    pub fn set_method(&mut self, method: OnsetMethod) {
+        self.config.method = method;
+        self.previous_magnitudes.iter_mut().for_each(|value| *value = 0.0);
+        self.previous_phases.iter_mut().for_each(|value| *value = 0.0);
+        self.second_previous_phases.iter_mut().for_each(|value| *value = 0.0);
+        self.previous_hfc = 0.0;
+        self.detection_history.clear();
+        self.previous_value = 0.0;
+        self.second_previous_value = 0.0;
+        self.frames_processed = 0;
+    }
+
+    // Clears all history without reallocating
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|sample| *sample = 0.0);
+        self.input_position = 0;
+        self.samples_until_hop = self.frame_size;
+        self.samples_seen = 0;
+        self.previous_magnitudes.iter_mut().for_each(|value| *value = 0.0);
+        self.previous_phases.iter_mut().for_each(|value| *value = 0.0);
+        self.second_previous_phases.iter_mut().for_each(|value| *value = 0.0);
+        self.previous_hfc = 0.0;
+        self.detection_history.clear();
+        self.previous_value = 0.0;
+        self.second_previous_value = 0.0;
+        self.frames_processed = 0;
+        self.last_onset_time = f32::NEG_INFINITY;
+    }
+
+    // Adds one sample. Returns an onset when the frame completed by this sample confirms one.
+    pub fn process_sample(&mut self, sample: f32) -> Option<Onset> {
+        self.input[self.input_position] = sample;
+        self.input_position = (self.input_position + 1) % self.frame_size;
+        self.samples_seen += 1;
+        self.samples_until_hop -= 1;
+
+        if self.samples_until_hop > 0 {
+            return None;
+        }
+        self.samples_until_hop = self.hop_size;
+
+        let value = self.analyse_frame();
+        self.pick_onset(value)
+    }
+
+    // Windows and transforms the current frame, then evaluates the detection function
+    fn analyse_frame(&mut self) -> f32 {
+        let frame_size = self.frame_size;
+
+        // input_position points at the oldest sample in the circular buffer. The FFT overwrites its
+        // buffer, so the padding is cleared every frame.
+        for i in 0..frame_size {
+            let sample = self.input[(self.input_position + i) % frame_size];
+            self.spectrum[i] = Complex { re: sample * self.window[i], im: 0.0 };
+        }
+        self.spectrum[frame_size..self.fft_size].iter_mut().for_each(|bin| *bin = Complex { re: 0.0, im: 0.0 });
+        self.fft.process(&mut self.spectrum);
+
+        let num_bins = self.previous_magnitudes.len();
+        let normalization = 1.0 / frame_size as f32;
+        let mut value = 0.0;
+        let mut hfc = 0.0;
+
+        for k in 0..num_bins {
+            let magnitude = self.spectrum[k].norm() * normalization;
+            let phase = self.spectrum[k].arg();
+
+            match self.config.method {
+                OnsetMethod::SpectralFlux => {
+                    value += (magnitude - self.previous_magnitudes[k]).max(0.0);
+                }
+                OnsetMethod::ComplexDomain => {
+                    // Only rising bins count, so that note releases do not register as onsets
+                    if magnitude >= self.previous_magnitudes[k] {
+                        let predicted_phase = 2.0 * self.previous_phases[k] - self.second_previous_phases[k];
+                        let predicted = Complex::from_polar(self.previous_magnitudes[k], predicted_phase);
+                        let actual = Complex::from_polar(magnitude, phase);
+                        value += (actual - predicted).norm();
+                    }
+                }
+                OnsetMethod::HighFrequencyContent => {
+                    hfc += k as f32 / num_bins as f32 * magnitude;
+                }
+            }
+
+            self.second_previous_phases[k] = self.previous_phases[k];
+            self.previous_phases[k] = phase;
+            self.previous_magnitudes[k] = magnitude;
+        }
+
+        if self.config.method == OnsetMethod::HighFrequencyContent {
+            value = (hfc - self.previous_hfc).max(0.0);
+            self.previous_hfc = hfc;
+        }
+
+        self.frames_processed += 1;
+        value
+    }
+
+    // Peak picking on the detection function. The previous value is an onset when it is a local
+    // maximum, above the adaptive threshold, and far enough away from the last onset.
+    fn pick_onset(&mut self, value: f32) -> Option<Onset> {
+        let candidate = self.previous_value;
+        let threshold = self.config.threshold_multiplier * self.median_history() + self.config.threshold_offset;
+        let is_peak = candidate > self.second_previous_value && candidate >= value;
+
+        // Push into the history, dropping the oldest value once the window is full
+        if self.config.threshold_window > 0 {
+            if self.detection_history.len() == self.config.threshold_window {
+                self.detection_history.remove(0);
+            }
+            self.detection_history.push(value);
+        }
+
+        self.second_previous_value = self.previous_value;
+        self.previous_value = value;
+
+        // The first frames are compared against silence, so skip them
+        if self.frames_processed < 3 || !is_peak || candidate <= threshold {
+            return None;
+        }
+
+        // The candidate belongs to the previous frame; its centre is half a frame plus one hop
+        // before the current position
+        let candidate_position = self.samples_seen as f32
+            - self.hop_size as f32
+            - self.frame_size as f32 / 2.0;
+        let time = candidate_position.max(0.0) / self.sample_rate;
+
+        if time - self.last_onset_time < self.config.min_interval {
+            return None;
+        }
+        self.last_onset_time = time;
+
+        Some(Onset { time, strength: candidate - threshold })
+    }
+
+    fn median_history(&mut self) -> f32 {
+        let len = self.detection_history.len();
+        if len == 0 {
+            return 0.0;
+        }
+
+        let scratch = &mut self.median_scratch[..len];
+        scratch.copy_from_slice(&self.detection_history);
+        let (_, median, _) = scratch.select_nth_unstable_by(len / 2, |a, b| a.total_cmp(b));
+        *median
+    }
+}
+
+// Offline onset detection over a whole signal
+pub fn detect_onsets(samples: &[f32], sample_rate: f32, config: OnsetConfig) -> Vec<Onset> {
+    let mut detector = OnsetDetector::new(config, sample_rate);
+
+    samples.iter()
+        .filter_map(|&sample| detector.process_sample(sample))
+        .collect()
+}
+
+// Onset detection function sampled once per hop over a whole signal. Returns the envelope and its
+// frame rate in frames per second.
+pub fn onset_envelope(samples: &[f32], sample_rate: f32, config: OnsetConfig) -> (Vec<f32>, f32) {
+    let frame_rate = sample_rate / config.hop_size(sample_rate) as f32;
+    let mut detector = OnsetDetector::new(config, sample_rate);
+    let mut envelope = Vec::new();
+
+    for &sample in samples {
+        let frames_before = detector.frames_processed();
+        detector.process_sample(sample);
+        if detector.frames_processed() != frames_before {
+            envelope.push(detector.detection_value());
+        }
+    }
+
+    (envelope, frame_rate)
+}
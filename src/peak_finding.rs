@@ -0,0 +1,462 @@
+// Minimum spacing between two peaks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeakDistance {
+    // Distance in bins
+    Bins(usize),
+
+    // Distance in cents. Assumes bin k lies at k times the bin width, as in an FFT spectrum, so the
+    // allowed spacing grows with frequency.
+    Cents(f32),
+}
+
+// Conditions a peak has to meet. Mirrors the arguments of scipy.signal.find_peaks; every
+// condition is optional.
+#[derive(Debug, Clone)]
+pub struct PeakConfig {
+    // Minimum value at the peak
+    pub height: Option<f32>,
+
+    // Minimum vertical distance between the peak and both of its direct neighbours
+    pub threshold: Option<f32>,
+
+    // Minimum spacing between peaks. Of two peaks closer than this, the lower one is dropped.
+    pub distance: Option<PeakDistance>,
+
+    // Minimum topographic prominence
+    pub prominence: Option<f32>,
+
+    // Length in bins of the window the bases are searched in, centred on the peak. Even lengths
+    // behave like the next odd one, and lengths below 2 search the whole signal.
+    pub wlen: Option<usize>,
+
+    // Minimum width in bins, measured at rel_height
+    pub width: Option<f32>,
+
+    // Height at which the width is measured, as a fraction of the prominence below the peak. 0.5
+    // measures the width at half prominence, 1.0 at the higher of the two bases.
+    pub rel_height: f32,
+}
+
+impl Default for PeakConfig {
+    fn default() -> Self {
+        Self {
+            height: None,
+            threshold: None,
+            distance: None,
+            prominence: None,
+            wlen: None,
+            width: None,
+            rel_height: 0.5,
+        }
+    }
+}
+
+// A peak and its measurements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    // Index of the peak. For a flat peak this is the middle of the plateau, rounded down.
+    pub index: usize,
+
+    // First and last index of the plateau. Equal to index for peaks that are not flat.
+    pub left_edge: usize,
+    pub right_edge: usize,
+
+    // Value at the peak
+    pub height: f32,
+
+    // Height of the peak above the higher of its two bases
+    pub prominence: f32,
+
+    // Lowest points between the peak and the nearest higher value on either side, or the end of
+    // the signal if there is none
+    pub left_base: usize,
+    pub right_base: usize,
+
+    // Width in bins at rel_height, and the interpolated positions where it was measured
+    pub width: f32,
+    pub left_ips: f32,
+    pub right_ips: f32,
+}
+
+// Finds peaks without allocating once it has been created. Holds at most max_peaks candidates per
+// call; candidates beyond that, counted from the lowest index, are ignored.
+pub struct PeakFinder {
+    config: PeakConfig,
+    max_peaks: usize,
+
+    // Peaks found by the most recent call to find
+    peaks: Vec<Peak>,
+
+    // Scratch space for the distance filter
+    order: Vec<usize>,
+    keep: Vec<bool>,
+}
+
+impl PeakFinder {
+    pub fn new(config: PeakConfig, max_peaks: usize) -> Self {
+        Self {
+            config,
+            max_peaks,
+            peaks: Vec::with_capacity(max_peaks),
+            order: Vec::with_capacity(max_peaks),
+            keep: Vec::with_capacity(max_peaks),
+        }
+    }
+
+    pub fn config(&self) -> &PeakConfig {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut PeakConfig {
+        &mut self.config
+    }
+
+    // Peaks found by the most recent call to find, lowest index first
+    pub fn peaks(&self) -> &[Peak] {
+        &self.peaks
+    }
+
+    // Finds every peak in values that meets the configured conditions, lowest index first
+    pub fn find(&mut self, values: &[f32]) -> &[Peak] {
+        self.peaks.clear();
+
+        find_local_maxima(values, &self.config, self.max_peaks, &mut self.peaks);
+
+        if let Some(distance) = self.config.distance {
+            filter_by_distance(distance, &mut self.peaks, &mut self.order, &mut self.keep);
+        }
+
+        let config = &self.config;
+        self.peaks.retain_mut(|peak| {
+            measure_prominence(values, config.wlen, peak);
+            measure_width(values, config.rel_height, peak);
+
+            config.prominence.is_none_or(|prominence| peak.prominence >= prominence)
+                && config.width.is_none_or(|width| peak.width >= width)
+        });
+
+        &self.peaks
+    }
+}
+
+// Finds peaks in a whole signal. Allocates, so use a PeakFinder on the real-time path.
+pub fn find_peaks(values: &[f32], config: &PeakConfig) -> Vec<Peak> {
+    let mut finder = PeakFinder::new(config.clone(), values.len() / 2 + 1);
+    finder.find(values);
+    finder.peaks
+}
+
+// Finds local maxima, including flat ones, and applies the height and threshold conditions. A
+// plateau only counts as a peak if both of its sides drop; the first and last value never do.
+fn find_local_maxima(values: &[f32], config: &PeakConfig, max_peaks: usize, peaks: &mut Vec<Peak>) {
+    if values.len() < 3 {
+        return;
+    }
+
+    let last = values.len() - 1;
+    let mut i = 1;
+
+    while i < last && peaks.len() < max_peaks {
+        if values[i - 1] >= values[i] {
+            i += 1;
+            continue;
+        }
+
+        // Walk to the end of a possible plateau
+        let mut ahead = i + 1;
+        while ahead < last && values[ahead] == values[i] {
+            ahead += 1;
+        }
+
+        if values[ahead] < values[i] {
+            let left_edge = i;
+            let right_edge = ahead - 1;
+            let index = (left_edge + right_edge) / 2;
+            let height = values[index];
+
+            // Like scipy, the threshold compares the middle sample with its direct neighbours, so
+            // plateaus wider than two samples only pass a threshold of zero
+            let passes_height = config.height.is_none_or(|min_height| height >= min_height);
+            let passes_threshold = config.threshold.is_none_or(|threshold| {
+                (height - values[index - 1]).min(height - values[index + 1]) >= threshold
+            });
+
+            if passes_height && passes_threshold {
+                peaks.push(Peak {
+                    index,
+                    left_edge,
+                    right_edge,
+                    height,
+                    prominence: 0.0,
+                    left_base: 0,
+                    right_base: 0,
+                    width: 0.0,
+                    left_ips: 0.0,
+                    right_ips: 0.0,
+                });
+            }
+        }
+
+        i = ahead;
+    }
+}
+
+// Drops peaks that are closer than the minimum distance to a higher peak. Peaks are handled from
+// highest to lowest, so every kept peak removes its lower neighbours.
+fn filter_by_distance(distance: PeakDistance, peaks: &mut Vec<Peak>, order: &mut Vec<usize>, keep: &mut Vec<bool>) {
+    order.clear();
+    order.extend(0..peaks.len());
+    order.sort_unstable_by(|&a, &b| peaks[b].height.total_cmp(&peaks[a].height).then(a.cmp(&b)));
+
+    keep.clear();
+    keep.resize(peaks.len(), true);
+
+    for &current in order.iter() {
+        if !keep[current] {
+            continue;
+        }
+
+        let (lowest, highest) = distance_bounds(peaks[current].index, distance);
+
+        for other in (0..current).rev() {
+            if (peaks[other].index as f32) <= lowest {
+                break;
+            }
+            keep[other] = false;
+        }
+
+        for other in current + 1..peaks.len() {
+            if (peaks[other].index as f32) >= highest {
+                break;
+            }
+            keep[other] = false;
+        }
+    }
+
+    let mut position = 0;
+    peaks.retain(|_| {
+        position += 1;
+        keep[position - 1]
+    });
+}
+
+// Indices a neighbouring peak has to be at or beyond to be far enough away from a peak at index
+fn distance_bounds(index: usize, distance: PeakDistance) -> (f32, f32) {
+    match distance {
+        PeakDistance::Bins(bins) => (index as f32 - bins as f32, index as f32 + bins as f32),
+        PeakDistance::Cents(cents) => {
+            let ratio = (cents / 1200.0).exp2();
+            (index as f32 / ratio, index as f32 * ratio)
+        }
+    }
+}
+
+// Topographic prominence. On each side, the base is the lowest point between the peak and the
+// nearest value higher than the peak, or the edge of the wlen window; the prominence is the peak's
+// height above the higher base.
+fn measure_prominence(values: &[f32], wlen: Option<usize>, peak: &mut Peak) {
+    let height = values[peak.index];
+
+    let (first, last) = match wlen {
+        Some(wlen) if wlen >= 2 => (peak.index.saturating_sub(wlen / 2), (peak.index + wlen / 2).min(values.len() - 1)),
+        _ => (0, values.len() - 1),
+    };
+
+    let mut left_base = peak.index;
+    let mut left_min = height;
+    for i in (first..=peak.index).rev() {
+        if values[i] > height {
+            break;
+        }
+        if values[i] < left_min {
+            left_min = values[i];
+            left_base = i;
+        }
+    }
+
+    let mut right_base = peak.index;
+    let mut right_min = height;
+    for (i, &value) in values.iter().enumerate().take(last + 1).skip(peak.index) {
+        if value > height {
+            break;
+        }
+        if value < right_min {
+            right_min = value;
+            right_base = i;
+        }
+    }
+
+    peak.left_base = left_base;
+    peak.right_base = right_base;
+    peak.prominence = height - left_min.max(right_min);
+}
+
+// Width of the peak at rel_height of its prominence below the peak, limited to the bases. The
+// crossing points are linearly interpolated between bins.
+fn measure_width(values: &[f32], rel_height: f32, peak: &mut Peak) {
+    let height = values[peak.index] - peak.prominence * rel_height;
+
+    let mut left = peak.index;
+    while left > peak.left_base && values[left] > height {
+        left -= 1;
+    }
+    let mut left_ips = left as f32;
+    if values[left] < height {
+        left_ips += (height - values[left]) / (values[left + 1] - values[left]);
+    }
+
+    let mut right = peak.index;
+    while right < peak.right_base && values[right] > height {
+        right += 1;
+    }
+    let mut right_ips = right as f32;
+    if values[right] < height {
+        right_ips -= (height - values[right]) / (values[right - 1] - values[right]);
+    }
+
+    peak.left_ips = left_ips;
+    peak.right_ips = right_ips;
+    peak.width = right_ips - left_ips;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected values are the output of scipy.signal.find_peaks, peak_prominences and peak_widths
+    // for the same signals and arguments
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} is not {}", actual, expected);
+    }
+
+    fn indices(peaks: &[Peak]) -> Vec<usize> {
+        peaks.iter().map(|peak| peak.index).collect()
+    }
+
+    #[test]
+    fn plateaus_are_peaks_at_their_middle() {
+        let peaks = find_peaks(&[0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0], &PeakConfig::default());
+
+        assert_eq!(indices(&peaks), [1, 5]);
+        assert_eq!((peaks[0].left_edge, peaks[0].right_edge), (1, 2));
+        assert_eq!((peaks[1].left_edge, peaks[1].right_edge), (4, 6));
+        assert_eq!((peaks[0].left_base, peaks[0].right_base), (0, 3));
+        assert_eq!((peaks[1].left_base, peaks[1].right_base), (3, 7));
+        assert_close(peaks[0].width, 2.0);
+        assert_close(peaks[1].width, 3.0);
+    }
+
+    #[test]
+    fn plateaus_that_rise_further_are_not_peaks() {
+        let peaks = find_peaks(&[0.0, 2.0, 2.0, 3.0, 0.0], &PeakConfig::default());
+
+        assert_eq!(indices(&peaks), [3]);
+    }
+
+    #[test]
+    fn first_and_last_values_are_never_peaks() {
+        assert!(find_peaks(&[2.0, 1.0, 0.0, 1.0, 2.0], &PeakConfig::default()).is_empty());
+        assert!(find_peaks(&[0.0, 2.0, 2.0], &PeakConfig::default()).is_empty());
+        assert!(find_peaks(&[1.0, 2.0], &PeakConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn prominence_uses_the_base_on_each_side() {
+        // The left base of the second peak (2 at index 2) is higher than its right base (0 at
+        // index 4), so the prominence is measured from the left one
+        let peaks = find_peaks(&[0.0, 6.0, 2.0, 5.0, 0.0], &PeakConfig::default());
+
+        assert_eq!(indices(&peaks), [1, 3]);
+        assert_close(peaks[0].prominence, 6.0);
+        assert_eq!((peaks[0].left_base, peaks[0].right_base), (0, 4));
+        assert_close(peaks[1].prominence, 3.0);
+        assert_eq!((peaks[1].left_base, peaks[1].right_base), (2, 4));
+    }
+
+    #[test]
+    fn widths_are_interpolated_at_rel_height() {
+        let values = [0.0, 6.0, 2.0, 5.0, 0.0];
+
+        let half = find_peaks(&values, &PeakConfig::default());
+        assert_close(half[0].width, 1.25);
+        assert_close(half[0].left_ips, 0.5);
+        assert_close(half[0].right_ips, 1.75);
+        assert_close(half[1].width, 0.8);
+        assert_close(half[1].left_ips, 2.5);
+        assert_close(half[1].right_ips, 3.3);
+
+        let full = find_peaks(&values, &PeakConfig { rel_height: 1.0, ..PeakConfig::default() });
+        assert_close(full[0].width, 4.0);
+        assert_close(full[1].width, 1.6);
+        assert_close(full[1].left_ips, 2.0);
+        assert_close(full[1].right_ips, 3.6);
+    }
+
+    #[test]
+    fn wlen_limits_the_base_search() {
+        let values = [0.0, 1.0, 4.0, 2.0, 3.0, 1.0, 0.0];
+
+        let whole = find_peaks(&values, &PeakConfig::default());
+        assert_eq!(indices(&whole), [2, 4]);
+        assert_close(whole[0].prominence, 4.0);
+        assert_eq!((whole[0].left_base, whole[0].right_base), (0, 6));
+        assert_close(whole[1].prominence, 1.0);
+        assert_eq!((whole[1].left_base, whole[1].right_base), (3, 6));
+
+        let narrow = find_peaks(&values, &PeakConfig { wlen: Some(3), ..PeakConfig::default() });
+        assert_close(narrow[0].prominence, 2.0);
+        assert_eq!((narrow[0].left_base, narrow[0].right_base), (1, 3));
+        assert_close(narrow[1].prominence, 1.0);
+        assert_eq!((narrow[1].left_base, narrow[1].right_base), (3, 5));
+
+        // An even window length behaves like the next odd one
+        for wlen in [4, 5] {
+            let peaks = find_peaks(&values, &PeakConfig { wlen: Some(wlen), ..PeakConfig::default() });
+            assert_close(peaks[0].prominence, 2.0);
+            assert_eq!((peaks[0].left_base, peaks[0].right_base), (0, 3));
+            assert_eq!((peaks[1].left_base, peaks[1].right_base), (3, 6));
+        }
+    }
+
+    #[test]
+    fn distance_keeps_the_highest_peaks() {
+        let values = [0.0, 5.0, 0.0, 4.0, 0.0, 6.0, 0.0, 3.0, 0.0];
+
+        let far = find_peaks(&values, &PeakConfig { distance: Some(PeakDistance::Bins(3)), ..PeakConfig::default() });
+        assert_eq!(indices(&far), [1, 5]);
+
+        let near = find_peaks(&values, &PeakConfig { distance: Some(PeakDistance::Bins(2)), ..PeakConfig::default() });
+        assert_eq!(indices(&near), [1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn conditions_filter_peaks() {
+        let values = [0.0, 6.0, 2.0, 5.0, 0.0];
+
+        let high = find_peaks(&values, &PeakConfig { height: Some(5.5), ..PeakConfig::default() });
+        assert_eq!(indices(&high), [1]);
+
+        let prominent = find_peaks(&values, &PeakConfig { prominence: Some(4.0), ..PeakConfig::default() });
+        assert_eq!(indices(&prominent), [1]);
+
+        let wide = find_peaks(&values, &PeakConfig { width: Some(1.0), ..PeakConfig::default() });
+        assert_eq!(indices(&wide), [1]);
+
+        let steep = find_peaks(&values, &PeakConfig { threshold: Some(3.5), ..PeakConfig::default() });
+        assert_eq!(indices(&steep), [1]);
+    }
+
+    #[test]
+    fn threshold_is_measured_at_the_middle_of_plateaus() {
+        let values = [0.0, 1.0, 1.0, 0.0, 2.0, 2.0, 2.0, 0.0, 3.0, 0.0];
+
+        let any = find_peaks(&values, &PeakConfig { threshold: Some(0.0), ..PeakConfig::default() });
+        assert_eq!(indices(&any), [1, 5, 8]);
+
+        // The middle of a two sample plateau is its left sample, which is level with its right
+        // neighbour; the middle of a wider one is level with both
+        let steep = find_peaks(&values, &PeakConfig { threshold: Some(0.5), ..PeakConfig::default() });
+        assert_eq!(indices(&steep), [8]);
+    }
+}
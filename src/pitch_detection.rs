@@ -0,0 +1,486 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::sync::Arc;
+use crate::error::{check_finite, check_sample_rate, AnalysisError};
+use crate::pitch_estimation::{PitchCandidate, PitchEstimator};
+
+// Settings shared by the monophonic pitch estimators
+#[derive(Debug, Clone)]
+pub struct MonoPitchConfig {
+    // Length of each analysis frame in milliseconds. Raised if needed to fit two periods of
+    // min_frequency.
+    pub frame_length_ms: f32,
+
+    // Time between the start of consecutive frames in milliseconds
+    pub hop_length_ms: f32,
+
+    // Pitch range searched, in Hz
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+
+    // YIN: the difference function has to dip below this for a frame to be voiced
+    pub yin_threshold: f32,
+
+    // MPM: a maximum counts if it reaches this fraction of the highest maximum
+    pub mpm_cutoff: f32,
+
+    // MPM: the chosen maximum has to reach this for a frame to be voiced
+    pub mpm_voicing_threshold: f32,
+
+    // Frames quieter than this are unvoiced, in dBFS
+    pub silence_threshold_db: f32,
+}
+
+impl Default for MonoPitchConfig {
+    fn default() -> Self {
+        Self {
+            // 2048 and 256 samples at 44.1 kHz
+            frame_length_ms: 46.4,
+            hop_length_ms: 5.8,
+            min_frequency: 50.0,
+            max_frequency: 1500.0,
+            yin_threshold: 0.15,
+            mpm_cutoff: 0.93,
+            mpm_voicing_threshold: 0.6,
+            silence_threshold_db: -60.0,
+        }
+    }
+}
+
+impl MonoPitchConfig {
+    // Lag range in samples for a sample rate
+    fn lag_range(&self, sample_rate: f32) -> (usize, usize) {
+        let min_lag = ((sample_rate / self.max_frequency).floor() as usize).max(2);
+        let max_lag = ((sample_rate / self.min_frequency).ceil() as usize).max(min_lag + 2);
+        (min_lag, max_lag)
+    }
+
+    fn frame_size(&self, sample_rate: f32) -> usize {
+        let (_, max_lag) = self.lag_range(sample_rate);
+        let frame_size = (self.frame_length_ms * sample_rate / 1000.0).round() as usize;
+        frame_size.max(2 * max_lag + 1)
+    }
+
+    fn hop_size(&self, sample_rate: f32) -> usize {
+        ((self.hop_length_ms * sample_rate / 1000.0).round() as usize).max(1)
+    }
+}
+
+// Shortest frame the lag analysis accepts: one lag has to fit twice
+const MIN_FRAME_SIZE: usize = 3;
+
+// Relative rounding error of correlations computed through the FFT
+const CORRELATION_TOLERANCE: f32 = 1e-5;
+
+// Lag-domain analysis shared by YIN and MPM. Both compute their lag functions from correlations
+// through the FFT, so a frame costs O(n log n). Buffers are rebuilt only when the frame length or
+// sample rate changes.
+struct LagAnalysis {
+    sample_rate: f32,
+    frame_size: usize,
+
+    // Lag range searched, in samples
+    min_lag: usize,
+    max_lag: usize,
+
+    // Running sums of the squared samples of the current frame
+    energy: Vec<f32>,
+
+    // FFT plans and scratch buffers for the correlation
+    forward_fft: Option<Arc<dyn Fft<f32>>>,
+    inverse_fft: Option<Arc<dyn Fft<f32>>>,
+    spectrum: Vec<Complex<f32>>,
+    reference_spectrum: Vec<Complex<f32>>,
+
+    // Lag function of the current frame, indexed by lag
+    lag_function: Vec<f32>,
+}
+
+impl LagAnalysis {
+    fn new() -> Self {
+        Self {
+            sample_rate: 0.0,
+            frame_size: 0,
+            min_lag: 0,
+            max_lag: 0,
+            energy: Vec::new(),
+            forward_fft: None,
+            inverse_fft: None,
+            spectrum: Vec::new(),
+            reference_spectrum: Vec::new(),
+            lag_function: Vec::new(),
+        }
+    }
+
+    // Sizes the buffers for a frame and computes its energy. Returns the RMS level of the frame
+    // in dBFS, or an error for frames and sample rates that cannot be analysed.
+    fn load(&mut self, frame: &[f32], sample_rate: f32, config: &MonoPitchConfig) -> Result<f32, AnalysisError> {
+        check_sample_rate(sample_rate)?;
+        check_finite(frame)?;
+        if frame.len() < MIN_FRAME_SIZE {
+            return Err(AnalysisError::FrameTooShort { required: MIN_FRAME_SIZE, actual: frame.len() });
+        }
+
+        if frame.len() != self.frame_size || sample_rate != self.sample_rate {
+            let (min_lag, max_lag) = config.lag_range(sample_rate);
+
+            // Short frames limit the longest period that fits twice
+            self.max_lag = max_lag.min(frame.len().saturating_sub(1) / 2).max(1);
+            self.min_lag = min_lag.min(self.max_lag);
+            self.sample_rate = sample_rate;
+            self.frame_size = frame.len();
+
+            let fft_size = (self.frame_size + self.max_lag).next_power_of_two();
+            let mut planner = FftPlanner::new();
+            self.forward_fft = Some(planner.plan_fft_forward(fft_size));
+            self.inverse_fft = Some(planner.plan_fft_inverse(fft_size));
+            self.spectrum = vec![Complex { re: 0.0, im: 0.0 }; fft_size];
+            self.reference_spectrum = vec![Complex { re: 0.0, im: 0.0 }; fft_size];
+            self.energy = vec![0.0; self.frame_size + 1];
+            self.lag_function = vec![0.0; self.max_lag + 2];
+        }
+
+        self.energy[0] = 0.0;
+        for (i, &sample) in frame.iter().enumerate() {
+            self.energy[i + 1] = self.energy[i] + sample * sample;
+        }
+
+        let rms = (self.energy[self.frame_size] / self.frame_size.max(1) as f32).sqrt();
+        Ok(20.0 * rms.max(1e-6).log10())
+    }
+
+    // Lags that fall inside a frequency range, limited to the lags the buffers were set up for.
+    // The range is empty if the first lag is greater than the last.
+    fn search_range(&self, min_frequency: f32, max_frequency: f32) -> (usize, usize) {
+        let first = ((self.sample_rate / max_frequency).ceil() as usize).max(self.min_lag);
+        let last = ((self.sample_rate / min_frequency).floor() as usize).min(self.max_lag);
+        (first, last)
+    }
+
+    // Writes the correlation of frame[..window] with the whole frame into the lag function, for
+    // lags up to max_lag: sum over j < window of frame[j] * frame[j + lag].
+    fn correlate(&mut self, frame: &[f32], window: usize) {
+        let (forward_fft, inverse_fft) = match (&self.forward_fft, &self.inverse_fft) {
+            (Some(forward_fft), Some(inverse_fft)) => (forward_fft, inverse_fft),
+            _ => return,
+        };
+
+        for (i, value) in self.spectrum.iter_mut().enumerate() {
+            *value = Complex { re: frame.get(i).copied().unwrap_or(0.0), im: 0.0 };
+        }
+        forward_fft.process(&mut self.spectrum);
+
+        if window == frame.len() {
+            // Autocorrelation: the reference is the signal itself
+            for value in self.spectrum.iter_mut() {
+                *value = Complex { re: value.norm_sqr(), im: 0.0 };
+            }
+        } else {
+            for (i, value) in self.reference_spectrum.iter_mut().enumerate() {
+                let sample = if i < window { frame[i] } else { 0.0 };
+                *value = Complex { re: sample, im: 0.0 };
+            }
+            forward_fft.process(&mut self.reference_spectrum);
+
+            for (value, reference) in self.spectrum.iter_mut().zip(self.reference_spectrum.iter()) {
+                *value = reference.conj() * *value;
+            }
+        }
+
+        inverse_fft.process(&mut self.spectrum);
+
+        let normalization = 1.0 / self.spectrum.len() as f32;
+        for (lag, value) in self.lag_function.iter_mut().enumerate() {
+            *value = self.spectrum[lag].re * normalization;
+        }
+    }
+}
+
+// YIN (de Cheveigné & Kawahara 2002). Picks the first dip of the cumulative mean normalized
+// difference function below a threshold.
+pub struct YinEstimator {
+    config: MonoPitchConfig,
+    lags: LagAnalysis,
+
+    // Frequency range searched within the configured one, in Hz
+    min_frequency: f32,
+    max_frequency: f32,
+}
+
+impl YinEstimator {
+    pub fn new(config: MonoPitchConfig) -> Self {
+        Self { config, lags: LagAnalysis::new(), min_frequency: 0.0, max_frequency: f32::INFINITY }
+    }
+}
+
+impl PitchEstimator for YinEstimator {
+    fn name(&self) -> &'static str {
+        "YIN"
+    }
+
+    fn frame_size(&self, sample_rate: f32) -> usize {
+        self.config.frame_size(sample_rate)
+    }
+
+    fn hop_size(&self, sample_rate: f32) -> usize {
+        self.config.hop_size(sample_rate)
+    }
+
+    fn set_frequency_range(&mut self, min_frequency: f32, max_frequency: f32) {
+        self.min_frequency = min_frequency;
+        self.max_frequency = max_frequency;
+    }
+
+    fn estimate(&mut self, frame: &[f32], sample_rate: f32, candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError> {
+        candidates.clear();
+
+        let level_db = self.lags.load(frame, sample_rate, &self.config)?;
+        if level_db < self.config.silence_threshold_db {
+            return Ok(());
+        }
+
+        let lags = &mut self.lags;
+        let window = lags.frame_size - lags.max_lag - 1;
+        lags.correlate(frame, window);
+
+        // Difference function d(lag) = e(0) + e(lag) - 2 r(lag), turned into the cumulative mean
+        // normalized difference in place. Differences within the rounding error of the FFT
+        // correlation count as zero, so that constant frames stay unvoiced instead of dipping on
+        // noise.
+        let reference_energy = lags.energy[window];
+        let mut running_sum = 0.0;
+        lags.lag_function[0] = 1.0;
+        for lag in 1..lags.lag_function.len() {
+            let lag_energy = lags.energy[lag + window] - lags.energy[lag];
+            let difference = reference_energy + lag_energy - 2.0 * lags.lag_function[lag];
+            let tolerance = CORRELATION_TOLERANCE * (reference_energy + lag_energy);
+            let difference = if difference > tolerance { difference } else { 0.0 };
+            running_sum += difference;
+            lags.lag_function[lag] = if running_sum > 0.0 { difference * lag as f32 / running_sum } else { 1.0 };
+        }
+
+        // First dip below the threshold, followed down to its minimum. Frames without one are
+        // unvoiced.
+        let (first_lag, last_lag) = lags.search_range(self.min_frequency, self.max_frequency);
+        let mut lag = first_lag;
+        while lag <= last_lag && lags.lag_function[lag] >= self.config.yin_threshold {
+            lag += 1;
+        }
+        if lag > last_lag {
+            return Ok(());
+        }
+        while lag < last_lag && lags.lag_function[lag + 1] < lags.lag_function[lag] {
+            lag += 1;
+        }
+
+        let refined_lag = lag as f32 + parabolic_offset(&lags.lag_function, lag);
+        candidates.push(PitchCandidate {
+            frequency: sample_rate / refined_lag,
+            salience: (1.0 - lags.lag_function[lag]).clamp(0.0, 1.0),
+            level_db,
+        });
+        Ok(())
+    }
+}
+
+// McLeod Pitch Method (McLeod & Wyvill 2005). Picks the first maximum of the normalized square
+// difference function that is close to the highest one.
+pub struct MpmEstimator {
+    config: MonoPitchConfig,
+    lags: LagAnalysis,
+
+    // Frequency range searched within the configured one, in Hz
+    min_frequency: f32,
+    max_frequency: f32,
+
+    // Lags of the key maxima of the current frame
+    key_maxima: Vec<usize>,
+}
+
+impl MpmEstimator {
+    pub fn new(config: MonoPitchConfig) -> Self {
+        Self {
+            config,
+            lags: LagAnalysis::new(),
+            min_frequency: 0.0,
+            max_frequency: f32::INFINITY,
+            key_maxima: Vec::new(),
+        }
+    }
+}
+
+impl PitchEstimator for MpmEstimator {
+    fn name(&self) -> &'static str {
+        "MPM"
+    }
+
+    fn frame_size(&self, sample_rate: f32) -> usize {
+        self.config.frame_size(sample_rate)
+    }
+
+    fn hop_size(&self, sample_rate: f32) -> usize {
+        self.config.hop_size(sample_rate)
+    }
+
+    fn set_frequency_range(&mut self, min_frequency: f32, max_frequency: f32) {
+        self.min_frequency = min_frequency;
+        self.max_frequency = max_frequency;
+    }
+
+    fn estimate(&mut self, frame: &[f32], sample_rate: f32, candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError> {
+        candidates.clear();
+
+        let level_db = self.lags.load(frame, sample_rate, &self.config)?;
+        if level_db < self.config.silence_threshold_db {
+            return Ok(());
+        }
+
+        let lags = &mut self.lags;
+        lags.correlate(frame, frame.len());
+
+        // Normalized square difference n(lag) = 2 r(lag) / m(lag), where m(lag) is the energy of
+        // the two overlapping parts
+        let n = lags.frame_size;
+        for lag in 0..lags.lag_function.len() {
+            let overlap_energy = lags.energy[n - lag] + (lags.energy[n] - lags.energy[lag]);
+            lags.lag_function[lag] = if overlap_energy > 0.0 { 2.0 * lags.lag_function[lag] / overlap_energy } else { 0.0 };
+        }
+
+        // Key maxima: the highest point of every positive region after the first zero crossing,
+        // within the lags searched
+        let (first_lag, last_lag) = lags.search_range(self.min_frequency, self.max_frequency);
+        let mut lag = 1;
+        while lag <= lags.max_lag && lags.lag_function[lag] > 0.0 {
+            lag += 1;
+        }
+
+        self.key_maxima.clear();
+        self.key_maxima.reserve(lags.max_lag / 2 + 1);
+
+        let mut region_max: Option<usize> = None;
+        for lag in lag..=lags.max_lag {
+            let value = lags.lag_function[lag];
+            if value > 0.0 {
+                if lag >= first_lag && lag <= last_lag && region_max.is_none_or(|max| value > lags.lag_function[max]) {
+                    region_max = Some(lag);
+                }
+            } else if let Some(max) = region_max.take() {
+                self.key_maxima.push(max);
+            }
+        }
+        self.key_maxima.extend(region_max);
+
+        // The first key maximum that comes close to the highest one
+        let highest = self.key_maxima.iter().map(|&max| lags.lag_function[max]).fold(0.0, f32::max);
+        let cutoff = self.config.mpm_cutoff * highest;
+        let chosen = self.key_maxima.iter().copied().find(|&max| lags.lag_function[max] >= cutoff);
+
+        if let Some(lag) = chosen {
+            let salience = lags.lag_function[lag].clamp(0.0, 1.0);
+            if salience >= self.config.mpm_voicing_threshold {
+                let refined_lag = lag as f32 + parabolic_offset(&lags.lag_function, lag);
+                candidates.push(PitchCandidate {
+                    frequency: sample_rate / refined_lag,
+                    salience,
+                    level_db,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Offset of the vertex of the parabola through values[index - 1..=index + 1], from -0.5 to 0.5
+pub fn parabolic_offset(values: &[f32], index: usize) -> f32 {
+    if index == 0 || index + 1 >= values.len() {
+        return 0.0;
+    }
+
+    let (left, centre, right) = (values[index - 1], values[index], values[index + 1]);
+    let denominator = left - 2.0 * centre + right;
+    if denominator.abs() < f32::EPSILON {
+        return 0.0;
+    }
+
+    (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn estimators() -> [Box<dyn PitchEstimator>; 2] {
+        [Box::new(YinEstimator::new(MonoPitchConfig::default())), Box::new(MpmEstimator::new(MonoPitchConfig::default()))]
+    }
+
+    // A frame of the length the estimator asks for, holding the given harmonics of frequency with
+    // amplitudes falling as 1 / harmonic
+    fn harmonic_frame(estimator: &dyn PitchEstimator, frequency: f32, harmonics: usize) -> Vec<f32> {
+        (0..estimator.frame_size(SAMPLE_RATE))
+            .map(|i| {
+                let time = i as f32 / SAMPLE_RATE;
+                (1..=harmonics)
+                    .map(|harmonic| 0.3 / harmonic as f32 * (2.0 * PI * frequency * harmonic as f32 * time).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn estimate(estimator: &mut dyn PitchEstimator, frame: &[f32]) -> Vec<PitchCandidate> {
+        let mut candidates = Vec::new();
+        estimator.estimate(frame, SAMPLE_RATE, &mut candidates).unwrap();
+        candidates
+    }
+
+    fn assert_pitch(estimator: &mut dyn PitchEstimator, frame: &[f32], expected: f32) {
+        let candidates = estimate(estimator, frame);
+        assert_eq!(candidates.len(), 1, "{} at {} Hz", estimator.name(), expected);
+
+        let cents = 1200.0 * (candidates[0].frequency / expected).log2();
+        assert!(cents.abs() < 5.0, "{} heard {} Hz for {} Hz", estimator.name(), candidates[0].frequency, expected);
+        assert!(candidates[0].salience > 0.8, "{} at {} Hz", estimator.name(), expected);
+    }
+
+    #[test]
+    fn sines_are_found_within_a_few_cents() {
+        for estimator in estimators().iter_mut() {
+            for frequency in [82.41, 196.0, 440.0, 1046.5] {
+                let frame = harmonic_frame(estimator.as_ref(), frequency, 1);
+                assert_pitch(estimator.as_mut(), &frame, frequency);
+            }
+        }
+    }
+
+    #[test]
+    fn harmonic_tones_are_found_at_their_fundamental() {
+        for estimator in estimators().iter_mut() {
+            for frequency in [82.41, 146.83, 329.63, 880.0] {
+                let frame = harmonic_frame(estimator.as_ref(), frequency, 8);
+                assert_pitch(estimator.as_mut(), &frame, frequency);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_and_dc_are_unvoiced() {
+        for estimator in estimators().iter_mut() {
+            let size = estimator.frame_size(SAMPLE_RATE);
+
+            assert!(estimate(estimator.as_mut(), &vec![0.0; size]).is_empty(), "{}", estimator.name());
+            assert!(estimate(estimator.as_mut(), &vec![0.5; size]).is_empty(), "{}", estimator.name());
+            assert!(estimate(estimator.as_mut(), &vec![-0.5; size]).is_empty(), "{}", estimator.name());
+        }
+    }
+
+    #[test]
+    fn parabolic_offset_finds_the_vertex() {
+        // Samples of -(x - 0.25)^2 around x = 0
+        let values = [-(1.25f32).powi(2), -(0.25f32).powi(2), -(0.75f32).powi(2)];
+        assert!((parabolic_offset(&values, 1) - 0.25).abs() < 1e-6);
+
+        assert_eq!(parabolic_offset(&values, 0), 0.0);
+        assert_eq!(parabolic_offset(&[1.0, 1.0, 1.0], 1), 0.0);
+    }
+}
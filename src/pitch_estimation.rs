@@ -0,0 +1,513 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::analysis_frame::FLOOR_DB;
+use crate::analyzer::{analysis_fft_size, power_levels_db, squared_magnitudes, CHUNK_SIZE, WINDOW_LENGTH_MS};
+use crate::error::{check_finite, check_sample_rate, AnalysisError};
+use crate::noise_floor::{NoiseFloorConfig, NoiseFloorEstimator};
+use crate::peak_finding::{PeakConfig, PeakDistance, PeakFinder};
+use crate::pitch_detection::{parabolic_offset, MonoPitchConfig, MpmEstimator, YinEstimator};
+use crate::SpectrumData;
+
+// A pitch found in one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchCandidate {
+    // Estimated frequency in Hz
+    pub frequency: f32,
+
+    // How strongly the frame supports this pitch, from 0 to 1
+    pub salience: f32,
+
+    // Level of the pitch in dBFS
+    pub level_db: f32,
+}
+
+// A pitch detection algorithm. Estimators are fed fixed-length frames of mono audio and report
+// every pitch they find, so different algorithms can be swapped in the analyzer or run side by
+// side on the same input.
+pub trait PitchEstimator: Send {
+    // Short name for displays and comparisons
+    fn name(&self) -> &'static str;
+
+    // Frame length the estimator needs at a sample rate, in samples
+    fn frame_size(&self, sample_rate: f32) -> usize;
+
+    // Number of samples between the start of consecutive frames
+    fn hop_size(&self, sample_rate: f32) -> usize;
+
+    // Limits the pitches reported to a frequency range in Hz. Estimators that cannot search the
+    // whole range keep their own, narrower limits.
+    fn set_frequency_range(&mut self, min_frequency: f32, max_frequency: f32);
+
+    // Forgets everything learned from previous frames, e.g. after a transport restart. Must not
+    // allocate.
+    fn reset(&mut self) {}
+
+    // Analyses one frame and replaces the contents of `candidates` with the pitches found,
+    // strongest first. Must not allocate once the estimator has seen a frame of this length at
+    // this sample rate, as long as `candidates` has room for every result. Frames with NaN or
+    // infinite samples and invalid sample rates are rejected with an error and leave
+    // `candidates` empty.
+    fn estimate(&mut self, frame: &[f32], sample_rate: f32, candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError>;
+
+    // Whether the estimator works on a magnitude spectrum. The analyzer then hands it the
+    // spectrum of its own FFT frame through estimate_spectrum instead of cutting sample frames.
+    fn uses_spectrum(&self) -> bool {
+        false
+    }
+
+    // Analyses the squared magnitudes of a Hann-windowed frame of window_size samples, zero-padded
+    // to twice the length of `spectrum`, with frames following each other without overlap.
+    // Otherwise works like estimate. Estimators that need samples report nothing.
+    fn estimate_spectrum(&mut self,
+                         _spectrum: &[SpectrumData],
+                         _sample_rate: f32,
+                         _window_size: usize,
+                         candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError> {
+        candidates.clear();
+        Ok(())
+    }
+}
+
+// The estimators available in the plugin, in the order of its detection modes
+pub fn default_estimators() -> Vec<Box<dyn PitchEstimator>> {
+    vec![
+        Box::new(FftPeakEstimator::new(FftPeakConfig::default())),
+        Box::new(YinEstimator::new(MonoPitchConfig::default())),
+        Box::new(MpmEstimator::new(MonoPitchConfig::default())),
+    ]
+}
+
+// Runs every estimator on the same frame and returns each one's name and candidates, or the
+// error it returned. Frames are trimmed to the most recent samples each estimator needs.
+pub fn compare_estimators(estimators: &mut [Box<dyn PitchEstimator>],
+                          frame: &[f32],
+                          sample_rate: f32) -> Vec<(&'static str, Result<Vec<PitchCandidate>, AnalysisError>)> {
+    estimators
+        .iter_mut()
+        .map(|estimator| {
+            let frame_size = estimator.frame_size(sample_rate).min(frame.len());
+            let mut candidates = Vec::new();
+            let result = estimator
+                .estimate(&frame[frame.len() - frame_size..], sample_rate, &mut candidates)
+                .map(|_| candidates);
+            (estimator.name(), result)
+        })
+        .collect()
+}
+
+// Settings for the FFT peak estimator
+#[derive(Debug, Clone)]
+pub struct FftPeakConfig {
+    // Frame length in milliseconds, zero-padded to the analyzer's FFT size. Also the hop length, so
+    // frames do not overlap.
+    pub window_length_ms: f32,
+
+    // Level a peak has to reach above the noise floor to count as a pitch, in dB
+    pub threshold_db: f32,
+
+    // Level a peak has to stand out from the spectrum around it, in dB
+    pub prominence_db: f32,
+
+    // Minimum spacing between two peaks. Keeps the sidelobes and split bins of one partial from
+    // being reported as neighbouring notes.
+    pub min_distance_cents: f32,
+
+    // Prominence at which a peak is reported with full salience, in dB
+    pub full_salience_prominence_db: f32,
+
+    // Most peaks reported per frame
+    pub max_peaks: usize,
+}
+
+impl Default for FftPeakConfig {
+    fn default() -> Self {
+        Self {
+            window_length_ms: WINDOW_LENGTH_MS,
+            threshold_db: 12.0,
+            prominence_db: 6.0,
+            min_distance_cents: 50.0,
+            full_salience_prominence_db: 40.0,
+            max_peaks: 512,
+        }
+    }
+}
+
+impl FftPeakConfig {
+    // Frame length in samples at a sample rate
+    pub fn window_size(&self, sample_rate: f32) -> usize {
+        ((self.window_length_ms * sample_rate / 1000.0).round() as usize).max(2)
+    }
+}
+
+// Polyphonic estimator that reports every prominent peak of a Hann-windowed magnitude spectrum.
+// Levels are measured against an adaptive noise floor, so the threshold follows the signal instead
+// of the loudest bin. In the analyzer it reads the spectrum of the analyzer's own FFT frame; its
+// FFT is only set up when it is fed samples directly.
+pub struct FftPeakEstimator {
+    config: FftPeakConfig,
+
+    // Sample rate, frame length and FFT size the sample buffers were set up for
+    sample_rate: f32,
+    window_size: usize,
+    fft_size: usize,
+
+    // Frequency range peaks are picked in, in Hz
+    min_frequency: f32,
+    max_frequency: f32,
+
+    fft: Option<Arc<dyn Fft<f32>>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+
+    // Squared magnitudes of the most recent sample frame
+    power: Vec<SpectrumData>,
+
+    // Number of bins and bin width in Hz the noise floor was set up for
+    num_bins: usize,
+    bin_width: f32,
+
+    // Level of every bin up to the Nyquist limit in dBFS, and its level above the noise floor
+    levels_db: Vec<f32>,
+    relative_levels_db: Vec<f32>,
+
+    noise_floor: NoiseFloorEstimator,
+    peak_finder: PeakFinder,
+}
+
+impl FftPeakEstimator {
+    pub fn new(config: FftPeakConfig) -> Self {
+        let peak_finder = PeakFinder::new(PeakConfig {
+            height: Some(config.threshold_db),
+            prominence: Some(config.prominence_db),
+            distance: Some(PeakDistance::Cents(config.min_distance_cents)),
+            ..PeakConfig::default()
+        }, config.max_peaks);
+
+        Self {
+            sample_rate: 0.0,
+            window_size: 0,
+            fft_size: 0,
+            min_frequency: 0.0,
+            max_frequency: f32::INFINITY,
+            fft: None,
+            window: Vec::new(),
+            spectrum: Vec::new(),
+            power: Vec::new(),
+            num_bins: 0,
+            bin_width: 0.0,
+            levels_db: Vec::new(),
+            relative_levels_db: Vec::new(),
+            noise_floor: NoiseFloorEstimator::new(NoiseFloorConfig::default(), 0, 1.0),
+            peak_finder,
+            config,
+        }
+    }
+
+    // Rebuilds the FFT plan and window table for a sample rate. Happens on the first sample frame
+    // and whenever the sample rate changes.
+    fn configure(&mut self, sample_rate: f32) {
+        let window_size = self.config.window_size(sample_rate);
+        let fft_size = analysis_fft_size(sample_rate, window_size);
+
+        self.sample_rate = sample_rate;
+        self.window_size = window_size;
+        self.fft_size = fft_size;
+        self.fft = Some(FftPlanner::new().plan_fft_forward(fft_size));
+        self.window = (0..window_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (window_size as f32 - 1.0)).cos())
+            .collect();
+        self.spectrum = vec![Complex { re: 0.0, im: 0.0 }; fft_size];
+        self.power = (0..fft_size / 2)
+            .map(|i| SpectrumData { frequency: i as f32 * sample_rate / fft_size as f32, power: 0.0 })
+            .collect();
+    }
+
+    // Rebuilds the level buffers and noise floor bands for a spectrum. Happens on the first
+    // spectrum and whenever its size or bin width changes.
+    fn configure_bins(&mut self, num_bins: usize, bin_width: f32) {
+        self.num_bins = num_bins;
+        self.bin_width = bin_width;
+        self.levels_db = Vec::with_capacity(num_bins);
+        self.relative_levels_db = Vec::with_capacity(num_bins);
+        self.noise_floor = NoiseFloorEstimator::new(NoiseFloorConfig::default(), num_bins, bin_width);
+    }
+}
+
+impl PitchEstimator for FftPeakEstimator {
+    fn name(&self) -> &'static str {
+        "FFT peaks"
+    }
+
+    fn frame_size(&self, sample_rate: f32) -> usize {
+        self.config.window_size(sample_rate)
+    }
+
+    fn hop_size(&self, sample_rate: f32) -> usize {
+        self.config.window_size(sample_rate)
+    }
+
+    fn set_frequency_range(&mut self, min_frequency: f32, max_frequency: f32) {
+        self.min_frequency = min_frequency;
+        self.max_frequency = max_frequency;
+    }
+
+    fn reset(&mut self) {
+        self.noise_floor.reset();
+    }
+
+    fn estimate(&mut self, frame: &[f32], sample_rate: f32, candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError> {
+        candidates.clear();
+        check_sample_rate(sample_rate)?;
+        check_finite(frame)?;
+
+        if sample_rate != self.sample_rate {
+            self.configure(sample_rate);
+        }
+
+        // Shorter frames are zero-padded at the start, and every frame at the end up to the FFT
+        // size. The FFT overwrites its buffer, so the padding is cleared every frame.
+        let window_size = self.window_size;
+        let frame = &frame[frame.len().saturating_sub(window_size)..];
+        let offset = window_size - frame.len();
+        let (start_padding, rest) = self.spectrum.split_at_mut(offset);
+        let (windowed, end_padding) = rest.split_at_mut(frame.len());
+        let mut windowed_chunks = windowed.chunks_exact_mut(CHUNK_SIZE);
+        let mut sample_chunks = frame.chunks_exact(CHUNK_SIZE);
+        let mut window_chunks = self.window[offset..].chunks_exact(CHUNK_SIZE);
+        for ((values, samples), window) in (&mut windowed_chunks).zip(&mut sample_chunks).zip(&mut window_chunks) {
+            for i in 0..CHUNK_SIZE {
+                values[i] = Complex { re: samples[i] * window[i], im: 0.0 };
+            }
+        }
+        for ((value, &sample), &window) in windowed_chunks.into_remainder().iter_mut()
+            .zip(sample_chunks.remainder())
+            .zip(window_chunks.remainder()) {
+            *value = Complex { re: sample * window, im: 0.0 };
+        }
+        start_padding.iter_mut().chain(end_padding).for_each(|value| *value = Complex { re: 0.0, im: 0.0 });
+        if let Some(fft) = &self.fft {
+            fft.process(&mut self.spectrum);
+        }
+
+        squared_magnitudes(&self.spectrum, &mut self.power);
+
+        // Taking the buffer out leaves an empty Vec behind, which does not allocate
+        let power = std::mem::take(&mut self.power);
+        let result = self.estimate_spectrum(&power, sample_rate, window_size, candidates);
+        self.power = power;
+        result
+    }
+
+    fn uses_spectrum(&self) -> bool {
+        true
+    }
+
+    fn estimate_spectrum(&mut self,
+                         spectrum: &[SpectrumData],
+                         sample_rate: f32,
+                         window_size: usize,
+                         candidates: &mut Vec<PitchCandidate>) -> Result<(), AnalysisError> {
+        candidates.clear();
+        check_sample_rate(sample_rate)?;
+        if spectrum.len() < 2 {
+            return Err(AnalysisError::FrameTooShort { required: 2, actual: spectrum.len() });
+        }
+
+        let num_bins = spectrum.len();
+        let bin_width = sample_rate / (2 * num_bins) as f32;
+        if num_bins != self.num_bins || bin_width != self.bin_width {
+            self.configure_bins(num_bins, bin_width);
+        }
+
+        // Only the bins in the frequency range, and one either side for the peak interpolation,
+        // are converted to decibels. The rest are reported at the floor level.
+        let first_bin = ((self.min_frequency / bin_width).ceil() as usize).min(num_bins);
+        let end_bin = ((self.max_frequency / bin_width).floor() as usize).saturating_add(1).clamp(first_bin, num_bins);
+        let measured = first_bin.saturating_sub(1)..(end_bin + 1).min(num_bins);
+
+        if spectrum[measured.clone()].iter().any(|bin| !bin.power.is_finite()) {
+            return Err(AnalysisError::NonFiniteInput);
+        }
+
+        // A full-scale sine produces a Hann-windowed peak of window_size / 4, so its squared
+        // magnitude is the square of that
+        let full_scale_power = (window_size as f32 / 4.0).powi(2);
+        self.levels_db.clear();
+        self.levels_db.resize(num_bins, FLOOR_DB);
+        power_levels_db(&spectrum[measured.clone()], full_scale_power, &mut self.levels_db[measured]);
+
+        self.noise_floor.update(&self.levels_db, window_size as f32 / sample_rate);
+
+        self.relative_levels_db.clear();
+        self.relative_levels_db.extend(self.levels_db
+            .iter()
+            .zip(self.noise_floor.floor_db().iter())
+            .map(|(level, floor)| level - floor));
+
+        // Bins outside the range are held at the noise floor, so they can neither become peaks
+        // nor limit the prominence of the peaks inside it
+        self.relative_levels_db[..first_bin].iter_mut().for_each(|level| *level = 0.0);
+        self.relative_levels_db[end_bin..].iter_mut().for_each(|level| *level = 0.0);
+
+        for peak in self.peak_finder.find(&self.relative_levels_db) {
+            // Interpolating the peak in dB gives a frequency well below the bin width
+            let position = peak.index as f32 + parabolic_offset(&self.levels_db, peak.index);
+
+            candidates.push(PitchCandidate {
+                frequency: position * bin_width,
+                salience: (peak.prominence / self.config.full_salience_prominence_db).clamp(0.0, 1.0),
+                level_db: self.levels_db[peak.index],
+            });
+        }
+
+        candidates.sort_unstable_by(|a, b| b.salience.total_cmp(&a.salience).then(b.level_db.total_cmp(&a.level_db)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MAX_SAMPLE_RATE;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    // A frame of the length the estimator asks for, holding a quiet sine
+    fn sine_frame(estimator: &dyn PitchEstimator) -> Vec<f32> {
+        (0..estimator.frame_size(SAMPLE_RATE))
+            .map(|i| 0.25 * (2.0 * PI * 220.0 * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    // Candidates left over from an earlier frame, which a failed estimate has to clear
+    fn stale_candidates() -> Vec<PitchCandidate> {
+        vec![PitchCandidate { frequency: 440.0, salience: 1.0, level_db: 0.0 }]
+    }
+
+    #[test]
+    fn non_finite_frames_are_rejected() {
+        for estimator in default_estimators().iter_mut() {
+            for invalid in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+                let mut frame = sine_frame(estimator.as_ref());
+                let middle = frame.len() / 2;
+                frame[middle] = invalid;
+
+                let mut candidates = stale_candidates();
+                let result = estimator.estimate(&frame, SAMPLE_RATE, &mut candidates);
+                assert_eq!(result, Err(AnalysisError::NonFiniteInput), "{} with {}", estimator.name(), invalid);
+                assert!(candidates.is_empty(), "{}", estimator.name());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_sample_rates_are_rejected() {
+        for estimator in default_estimators().iter_mut() {
+            let frame = sine_frame(estimator.as_ref());
+
+            for sample_rate in [0.0, -44100.0, f32::NAN, f32::INFINITY, MAX_SAMPLE_RATE * 2.0] {
+                let mut candidates = stale_candidates();
+                let result = estimator.estimate(&frame, sample_rate, &mut candidates);
+                assert!(matches!(result, Err(AnalysisError::InvalidSampleRate(_))), "{} at {}", estimator.name(), sample_rate);
+                assert!(candidates.is_empty(), "{}", estimator.name());
+            }
+        }
+    }
+
+    #[test]
+    fn short_frames_are_rejected_or_padded() {
+        for estimator in default_estimators().iter_mut() {
+            let mut candidates = stale_candidates();
+            let result = estimator.estimate(&[0.5, -0.5], SAMPLE_RATE, &mut candidates);
+
+            // The FFT estimator zero-pads short frames; the lag-based ones need a few samples
+            if estimator.name() == "FFT peaks" {
+                assert_eq!(result, Ok(()));
+            } else {
+                assert_eq!(result, Err(AnalysisError::FrameTooShort { required: 3, actual: 2 }), "{}", estimator.name());
+            }
+            assert!(candidates.is_empty(), "{}", estimator.name());
+        }
+    }
+
+    #[test]
+    fn denormal_frames_are_analysed() {
+        for estimator in default_estimators().iter_mut() {
+            let frame: Vec<f32> = sine_frame(estimator.as_ref())
+                .iter()
+                .map(|sample| sample * f32::MIN_POSITIVE)
+                .collect();
+            assert!(frame.iter().any(|sample| sample.is_subnormal()));
+
+            let mut candidates = Vec::new();
+            assert_eq!(estimator.estimate(&frame, SAMPLE_RATE, &mut candidates), Ok(()), "{}", estimator.name());
+            assert!(candidates.iter().all(|candidate| candidate.frequency.is_finite() && candidate.level_db.is_finite()),
+                    "{}", estimator.name());
+        }
+    }
+
+    #[test]
+    fn fft_peaks_measure_a_sine() {
+        let mut estimator = FftPeakEstimator::new(FftPeakConfig::default());
+        estimator.set_frequency_range(100.0, 1000.0);
+        let frame: Vec<f32> = (0..estimator.frame_size(SAMPLE_RATE))
+            .map(|i| 0.5 * (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE).sin())
+            .collect();
+
+        let mut candidates = Vec::new();
+        assert_eq!(estimator.estimate(&frame, SAMPLE_RATE, &mut candidates), Ok(()));
+
+        // Half of full scale is 6 dB down
+        let strongest = candidates[0];
+        assert!((strongest.frequency - 440.0).abs() < 0.1, "{}", strongest.frequency);
+        assert!((strongest.level_db + 6.0).abs() < 0.5, "{}", strongest.level_db);
+    }
+
+    #[test]
+    fn comparison_reports_each_estimator_error() {
+        let mut estimators = default_estimators();
+        let frame = [0.0, f32::NAN, 0.0, 0.0];
+
+        for (name, result) in compare_estimators(&mut estimators, &frame, SAMPLE_RATE) {
+            assert_eq!(result, Err(AnalysisError::NonFiniteInput), "{}", name);
+        }
+    }
+
+    #[test]
+    fn fft_peaks_read_a_spectrum_computed_elsewhere() {
+        // The spectrum of a Hann-windowed half-scale sine, as the analyzer computes it
+        let window_size = FftPeakConfig::default().window_size(SAMPLE_RATE);
+        let fft_size = analysis_fft_size(SAMPLE_RATE, window_size);
+        let mut buffer: Vec<Complex<f32>> = (0..fft_size)
+            .map(|i| {
+                let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / (window_size as f32 - 1.0)).cos();
+                let sample = if i < window_size { 0.5 * (2.0 * PI * 330.0 * i as f32 / SAMPLE_RATE).sin() } else { 0.0 };
+                Complex { re: sample * window, im: 0.0 }
+            })
+            .collect();
+        FftPlanner::new().plan_fft_forward(fft_size).process(&mut buffer);
+        let spectrum: Vec<SpectrumData> = buffer[..fft_size / 2]
+            .iter()
+            .enumerate()
+            .map(|(i, value)| SpectrumData { frequency: i as f32 * SAMPLE_RATE / fft_size as f32, power: value.norm_sqr() })
+            .collect();
+
+        let mut estimator = FftPeakEstimator::new(FftPeakConfig::default());
+        estimator.set_frequency_range(100.0, 1000.0);
+        assert!(estimator.uses_spectrum());
+
+        let mut candidates = Vec::new();
+        assert_eq!(estimator.estimate_spectrum(&spectrum, SAMPLE_RATE, window_size, &mut candidates), Ok(()));
+        assert!((candidates[0].frequency - 330.0).abs() < 0.1, "{}", candidates[0].frequency);
+        // Half of full scale is 6 dB down, less up to 1.4 dB when the sine falls between bins
+        assert!(candidates[0].level_db < -6.0 + 0.1 && candidates[0].level_db > -6.0 - 1.5, "{}", candidates[0].level_db);
+
+        // The lag-based estimators need samples
+        for estimator in default_estimators().iter_mut().filter(|estimator| !estimator.uses_spectrum()) {
+            let mut candidates = stale_candidates();
+            assert_eq!(estimator.estimate_spectrum(&spectrum, SAMPLE_RATE, window_size, &mut candidates), Ok(()));
+            assert!(candidates.is_empty(), "{}", estimator.name());
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::{DetectionMode, OnsetMethod};
+
+// Extension of user preset files
+const PRESET_EXTENSION: &str = "json";
+
+// A named set of detection settings. Covers every parameter that decides what is detected. The
+// routing parameters (analysis source, channel mode and analysis channel) are deliberately left
+// out, like the display settings: they depend on the track the plugin is inserted on rather than
+// on the instrument, so loading a preset keeps them as they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+
+    pub detection_mode: DetectionMode,
+
+    // Frequency range analysed, in Hz
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+
+    // Level a pitch has to reach to start a note, in dBFS
+    pub note_threshold_db: f32,
+
+    // Whether percussion is filtered out before detection. Missing from presets saved before the
+    // filter existed, which load with it off.
+    #[serde(default)]
+    pub suppress_percussion: bool,
+
+    // Detection function used to find attacks. Presets saved before it could be chosen load with
+    // spectral flux, which was used until then.
+    #[serde(default)]
+    pub onset_method: OnsetMethod,
+}
+
+// Presets shipped with the plugin
+pub fn factory_presets() -> Vec<Preset> {
+    let preset = |name: &str, detection_mode, min_frequency, max_frequency, note_threshold_db, suppress_percussion, onset_method| {
+        Preset {
+            name: name.to_string(),
+            detection_mode,
+            min_frequency,
+            max_frequency,
+            note_threshold_db,
+            suppress_percussion,
+            onset_method,
+        }
+    };
+
+    vec![
+        // A0 to C8
+        preset("Piano", DetectionMode::Polyphonic, 26.0, 4435.0, -48.0, false, OnsetMethod::SpectralFlux),
+        // E2 to E6, with a lower threshold for the quieter upper strings
+        preset("Guitar", DetectionMode::Polyphonic, 78.0, 1400.0, -54.0, false, OnsetMethod::SpectralFlux),
+        // A#0 to G#4. The analysis window of about 1.5 s, shared by all presets, is long enough to
+        // resolve the closely spaced low notes.
+        preset("Bass", DetectionMode::Polyphonic, 29.0, 415.0, -48.0, false, OnsetMethod::ComplexDomain),
+        // E2 to C6. A single voice is tracked with much lower latency in monophonic mode, and sung
+        // note changes are found by their change in pitch rather than in level.
+        preset("Vocal", DetectionMode::Mpm, 78.0, 1110.0, -54.0, false, OnsetMethod::ComplexDomain),
+        // C2 (cello) to E7 (violin). Bowed notes start too softly for spectral flux.
+        preset("Strings", DetectionMode::Polyphonic, 62.0, 2800.0, -54.0, false, OnsetMethod::ComplexDomain),
+        // Fundamentals of most pitched parts, with the drums filtered out. A higher threshold keeps
+        // what is left of them and noise out.
+        preset("Full Mix", DetectionMode::Polyphonic, 40.0, 2000.0, -40.0, true, OnsetMethod::SpectralFlux),
+    ]
+}
+
+// The plugin's folder in the platform's configuration folder, if that can be found
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    config_dir.map(|config_dir| config_dir.join("Fourier Chords"))
+}
+
+// Folder user presets are stored in
+pub fn user_preset_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("Presets"))
+}
+
+// Every readable user preset, sorted by name. Files that cannot be read or parsed are skipped.
+pub fn load_user_presets() -> Vec<Preset> {
+    let entries = match user_preset_dir().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return Vec::new(),
+    };
+
+    let mut presets: Vec<Preset> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == PRESET_EXTENSION))
+        .filter_map(|path| load_preset(&path).ok())
+        .collect();
+
+    presets.sort_by_key(|preset| preset.name.to_lowercase());
+    presets
+}
+
+pub fn load_preset(path: &Path) -> io::Result<Preset> {
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str(&contents).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Writes a preset to the user preset folder, named after the preset. An existing preset with the
+// same name is replaced. Returns the path of the file.
+pub fn save_user_preset(preset: &Preset) -> io::Result<PathBuf> {
+    check_name(preset)?;
+    let dir = user_preset_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no configuration folder found"))?;
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!("{}.{}", file_stem(&preset.name), PRESET_EXTENSION));
+    save_preset(preset, &path)?;
+    Ok(path)
+}
+
+// Writes a preset to a file. Presets without a name are refused.
+pub fn save_preset(preset: &Preset, path: &Path) -> io::Result<()> {
+    check_name(preset)?;
+    let contents = serde_json::to_string_pretty(preset)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    fs::write(path, contents)
+}
+
+// Presets are listed and saved under their name, so one without a name cannot be told apart
+fn check_name(preset: &Preset) -> io::Result<()> {
+    if preset.name.trim().is_empty() {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "the preset has no name"))
+    } else {
+        Ok(())
+    }
+}
+
+// File name for a preset name, with characters that are not safe on every platform replaced. The
+// name must not be empty.
+fn file_stem(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file in the temporary folder that is removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(env::temp_dir().join(format!("fourier-chords-{}-{}.{}", name, std::process::id(), PRESET_EXTENSION)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn presets_survive_a_round_trip() {
+        for preset in factory_presets() {
+            let json = serde_json::to_string_pretty(&preset).unwrap();
+            assert_eq!(serde_json::from_str::<Preset>(&json).unwrap(), preset);
+
+            let file = TempFile::new(&file_stem(&preset.name));
+            save_preset(&preset, &file.0).unwrap();
+            assert_eq!(load_preset(&file.0).unwrap(), preset);
+        }
+    }
+
+    #[test]
+    fn older_presets_load_with_defaults() {
+        let json = r#"{
+            "name": "Old",
+            "detection_mode": "Yin",
+            "min_frequency": 80.0,
+            "max_frequency": 1000.0,
+            "note_threshold_db": -50.0
+        }"#;
+
+        let preset: Preset = serde_json::from_str(json).unwrap();
+        assert_eq!(preset.detection_mode, DetectionMode::Yin);
+        assert!(!preset.suppress_percussion);
+        assert_eq!(preset.onset_method, OnsetMethod::SpectralFlux);
+    }
+
+    #[test]
+    fn presets_without_a_name_are_refused() {
+        let file = TempFile::new("unnamed");
+
+        for name in ["", "   "] {
+            let preset = Preset { name: name.to_string(), ..factory_presets()[0].clone() };
+            let error = save_preset(&preset, &file.0).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(!file.0.exists());
+        }
+    }
+
+    #[test]
+    fn file_names_only_keep_safe_characters() {
+        assert_eq!(file_stem(" Lead Guitar "), "Lead Guitar");
+        assert_eq!(file_stem("A/B: mix?"), "A_B_ mix_");
+        assert_eq!(file_stem("Bass_2-old"), "Bass_2-old");
+    }
+}
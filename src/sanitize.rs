@@ -0,0 +1,49 @@
+// Replaces NaN and infinite samples with silence and flushes denormals to zero. Denormals are
+// valid but can make every operation on them many times slower, which adds up in the feedback
+// loops of the analysis.
+pub fn sanitize_sample(sample: f32) -> f32 {
+    if sample.is_finite() && sample.abs() >= f32::MIN_POSITIVE {
+        sample
+    } else {
+        0.0
+    }
+}
+
+// Flushes denormal values to zero in place
+pub fn flush_denormals(values: &mut [f32]) {
+    for value in values.iter_mut() {
+        if value.abs() < f32::MIN_POSITIVE {
+            *value = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_samples_become_silence() {
+        for sample in [f32::NAN, -f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(sanitize_sample(sample), 0.0);
+        }
+    }
+
+    #[test]
+    fn denormals_are_flushed_to_zero() {
+        for sample in [f32::MIN_POSITIVE / 2.0, -f32::MIN_POSITIVE / 2.0, f32::from_bits(1)] {
+            assert_eq!(sanitize_sample(sample), 0.0);
+        }
+
+        let mut values = [0.5, f32::MIN_POSITIVE / 4.0, -1.0, -f32::from_bits(1), f32::MIN_POSITIVE];
+        flush_denormals(&mut values);
+        assert_eq!(values, [0.5, 0.0, -1.0, 0.0, f32::MIN_POSITIVE]);
+    }
+
+    #[test]
+    fn valid_samples_pass_unchanged() {
+        for sample in [0.0, -0.0, 0.25, -1.0, 8.0, f32::MIN_POSITIVE, -f32::MIN_POSITIVE, f32::MAX] {
+            assert_eq!(sanitize_sample(sample), sample);
+        }
+    }
+}